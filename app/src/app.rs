@@ -1,15 +1,19 @@
 use crate::Config;
+use crate::mouse::{MouseAction, MouseButton, MouseEvent};
+use crate::notify::{Notification, Notifier};
+use crate::search::SearchBar;
 use crate::shaper::Shaper;
 use arboard::Clipboard;
 use crossbeam_channel::{Receiver, unbounded};
 use glyphon::{FontSystem, SwashCache, fontdb};
 use portable_pty::PtySize;
+use screen_grid::{Direction, Point};
 use std::collections::{HashMap, VecDeque};
 use std::sync::Mutex;
 use std::thread::JoinHandle;
 use std::time::{Duration, Instant};
 use std::{sync::Arc, thread};
-use winit::event::MouseScrollDelta;
+use winit::event::{KeyEvent, MouseScrollDelta};
 use winit::event_loop::EventLoopProxy;
 use winit::keyboard::ModifiersState;
 
@@ -37,10 +41,19 @@ pub struct App {
     pty_data_receiver: Option<Receiver<Vec<u8>>>,
     proxy: Option<EventLoopProxy<CustomEvent>>,
     clipboard: Option<Clipboard>,
-    selection_start: Option<(usize, usize)>, // (col, row)
-    selection_end: Option<(usize, usize)>,   // (col, row)
+    notifier: Box<dyn Notifier>,
+    /// Selection ends, which may be anywhere in history
+    selection_start: Option<Point>,
+    selection_end: Option<Point>,
     is_mouse_dragging: bool,
     hovered_link_id: Option<u32>,
+    /// Button held down while mouse reporting, for drag reports
+    mouse_button_held: Option<MouseButton>,
+    /// Last cell a motion report was sent for
+    last_reported_cell: Option<(usize, usize)>,
+    search: Option<SearchBar>,
+    /// Title last applied to the window
+    window_title: String,
 
     font_system: Option<FontSystem>,
     swash_cache: Option<SwashCache>,
@@ -53,12 +66,21 @@ pub struct App {
 }
 
 impl App {
-    pub fn new(proxy: EventLoopProxy<CustomEvent>, config: Arc<Config>) -> Self {
+    pub fn new(
+        proxy: EventLoopProxy<CustomEvent>,
+        config: Arc<Config>,
+        notifier: Box<dyn Notifier>,
+    ) -> Self {
         Self {
             proxy: Some(proxy),
             clipboard: Clipboard::new().ok(),
+            notifier,
             is_mouse_dragging: false,
             hovered_link_id: None,
+            mouse_button_held: None,
+            last_reported_cell: None,
+            search: None,
+            window_title: String::new(),
             renderer: None,
             term: None,
             pty: None,
@@ -86,53 +108,76 @@ impl App {
 
         let term_lock = self.term.as_ref()?.lock().ok()?;
 
-        let (start, end) =
-            if start_pos.1 < end_pos.1 || (start_pos.1 == end_pos.1 && start_pos.0 <= end_pos.0) {
-                (start_pos, end_pos)
-            } else {
-                (end_pos, start_pos)
-            };
+        let start = start_pos.min(end_pos);
+        let end = start_pos.max(end_pos);
+        let result = term_lock.grid().text_in_range(start, end);
 
-        let (start_col, start_row) = start;
-        let (end_col, end_row) = end;
-
-        let mut result = String::new();
+        if result.is_empty() {
+            None
+        } else {
+            Some(result)
+        }
+    }
 
-        for y in start_row..=end_row {
-            // Add a newline for every line after the first one in the selection
-            if y > start_row {
-                result.push('\n');
+    /// Load the config file again and hand it to everything that holds it.
+    /// A config that fails to load is logged and the current one kept
+    fn reload_config(&mut self) {
+        let config = match Config::load() {
+            Ok(config) => Arc::new(config),
+            Err(e) => {
+                log::warn!("Failed to reload config: {e}");
+                return;
             }
+        };
 
-            if let Some(row) = term_lock.grid().get_display_row(y, term_lock.scroll_offset) {
-                let line_start = if y == start_row { start_col } else { 0 };
-                let line_end = if y == end_row {
-                    end_col
-                } else {
-                    term_lock.grid().cols
-                };
+        if let Some(renderer) = &mut self.renderer {
+            renderer.set_config(config.clone());
+        }
+        if let Some(term_arc) = &self.term {
+            term_arc.lock().unwrap().set_config(config.clone());
+        }
+        self.config = config;
+    }
+
+    /// Handle a key press while the search bar is open
+    fn handle_search_key(&mut self, event: &KeyEvent) {
+        use winit::keyboard::{Key, NamedKey};
 
-                let line_text: String = row
-                    .cells
-                    .iter()
-                    .skip(line_start)
-                    .take(line_end.saturating_sub(line_start))
-                    .map(|cell| cell.ch)
-                    .collect();
-
-                // For multi-line selections, trim trailing whitespace from all but the last line
-                if y < end_row {
-                    result.push_str(line_text.trim_end());
+        let (Some(search), Some(term_arc)) = (&mut self.search, &self.term) else {
+            return;
+        };
+        let mut term = term_arc.lock().unwrap();
+
+        match &event.logical_key {
+            Key::Named(NamedKey::Escape) => {
+                self.search = None;
+                term.is_dirty = true;
+            }
+            Key::Named(NamedKey::Enter) => {
+                let direction = if self.modifiers.shift_key() {
+                    Direction::Forward
                 } else {
-                    result.push_str(&line_text);
+                    Direction::Backward
+                };
+                search.step(&mut term, direction);
+            }
+            Key::Named(NamedKey::Backspace) => {
+                let mut query = search.query.clone();
+                query.pop();
+                search.set_query(query, &mut term);
+            }
+            _ => {
+                if let Some(text) = &event.text
+                    && !text.chars().any(char::is_control)
+                {
+                    let query = format!("{}{text}", search.query);
+                    search.set_query(query, &mut term);
                 }
             }
         }
 
-        if result.is_empty() {
-            None
-        } else {
-            Some(result)
+        if let Some(renderer) = &self.renderer {
+            renderer.window.request_redraw();
         }
     }
 }
@@ -170,6 +215,7 @@ impl ApplicationHandler<CustomEvent> for App {
             self.swash_cache = Some(SwashCache::new());
             self.fallback_cache = Some(HashMap::new());
 
+            #[cfg_attr(not(target_os = "macos"), allow(unused_mut))]
             let mut window_attributes =
                 WindowAttributes::default().with_transparent(self.config.background_opacity < 1.0);
 
@@ -205,7 +251,7 @@ impl ApplicationHandler<CustomEvent> for App {
                 self.config.clone(),
             )));
 
-            let pty = spawn_shell(cols as u16, rows as u16, self.config.clone());
+            let pty = spawn_shell(cols as u16, rows as u16, self.config.clone(), None);
 
             // Create a channel
             let (tx, rx) = unbounded();
@@ -274,6 +320,11 @@ impl ApplicationHandler<CustomEvent> for App {
                 WindowEvent::ModifiersChanged(new_modifiers) => {
                     self.modifiers = new_modifiers.state();
                 }
+                WindowEvent::Focused(focused) => {
+                    if let Some(term_arc) = &self.term {
+                        term_arc.lock().unwrap().focused = focused;
+                    }
+                }
                 WindowEvent::CloseRequested => {
                     println!("Window close requested. Exiting");
                     event_loop.exit();
@@ -288,12 +339,10 @@ impl ApplicationHandler<CustomEvent> for App {
                         0.0,
                     );
 
-                    if let Some(term_arc) = &self.term {
-                        if let Ok(mut t) = term_arc.lock() {
-                            t.normal_grid.resize(cols, rows);
-                            t.alternate_grid.resize(cols, rows);
-                            t.is_dirty = true;
-                        }
+                    if let Some(term_arc) = &self.term
+                        && let Ok(mut t) = term_arc.lock()
+                    {
+                        t.resize(cols, rows);
                     }
 
                     if let Some(pty) = &self.pty {
@@ -323,6 +372,7 @@ impl ApplicationHandler<CustomEvent> for App {
                         let processing_budget = Duration::from_millis(12);
 
                         let more_shaping_work: bool;
+                        let notifications: Vec<Notification>;
                         {
                             let mut term = term_arc.lock().unwrap();
 
@@ -342,12 +392,45 @@ impl ApplicationHandler<CustomEvent> for App {
                                 }
                             }
 
+                            // Answer any queries the program made
+                            let replies = term.take_replies();
+                            if !replies.is_empty()
+                                && let Some(pty) = &mut self.pty
+                            {
+                                use std::io::Write;
+                                let _ = pty.writer.write_all(&replies);
+                            }
+
+                            // Clipboard access the program asked for
+                            for request in term.take_clipboard_requests() {
+                                if let Some(clipboard) = &mut self.clipboard
+                                    && let Some(reply) = request.execute(clipboard)
+                                    && let Some(pty) = &mut self.pty
+                                {
+                                    use std::io::Write;
+                                    let _ = pty.writer.write_all(&reply);
+                                }
+                            }
+
+                            notifications = term.take_notifications();
+
+                            let title = term.window_title();
+                            if title != self.window_title {
+                                renderer.window.set_title(&title);
+                                self.window_title = title;
+                            }
+
                             // After parsing, we shape a fixed number of lines
                             let mut shaper = Shaper::new(self.config.clone());
                             more_shaping_work =
                                 shaper.shape_budgeted(font_system, fallback_cache, &mut term, 400);
                         }
 
+                        // Sent once the terminal is unlocked again
+                        for notification in notifications {
+                            self.notifier.notify(&notification);
+                        }
+
                         let mut term_lock = term_arc.lock().unwrap();
                         let selection = if let (Some(start), Some(end)) =
                             (self.selection_start, self.selection_end)
@@ -363,6 +446,7 @@ impl ApplicationHandler<CustomEvent> for App {
                             &mut term_lock,
                             selection,
                             self.hovered_link_id,
+                            self.search.as_ref(),
                             #[cfg(target_os = "macos")]
                             self.top_padding,
                             #[cfg(not(target_os = "macos"))]
@@ -375,6 +459,38 @@ impl ApplicationHandler<CustomEvent> for App {
                     }
                 }
                 WindowEvent::MouseInput { state, button, .. } => {
+                    let pressed = state == winit::event::ElementState::Pressed;
+                    let mouse_button = match button {
+                        winit::event::MouseButton::Left => Some(MouseButton::Left),
+                        winit::event::MouseButton::Middle => Some(MouseButton::Middle),
+                        winit::event::MouseButton::Right => Some(MouseButton::Right),
+                        _ => None,
+                    };
+
+                    if let Some(mouse_button) = mouse_button {
+                        let action = if pressed {
+                            MouseAction::Press
+                        } else {
+                            MouseAction::Release
+                        };
+                        self.mouse_button_held = pressed.then_some(mouse_button);
+
+                        if report_mouse(
+                            &self.term,
+                            &mut self.pty,
+                            renderer,
+                            self.modifiers,
+                            action,
+                            Some(mouse_button),
+                            #[cfg(target_os = "macos")]
+                            self.top_padding,
+                            #[cfg(not(target_os = "macos"))]
+                            0.0,
+                        ) {
+                            return;
+                        }
+                    }
+
                     if button == winit::event::MouseButton::Left {
                         if state == winit::event::ElementState::Pressed {
                             #[cfg(target_os = "macos")]
@@ -390,40 +506,60 @@ impl ApplicationHandler<CustomEvent> for App {
                                     #[cfg(not(target_os = "macos"))]
                                     0.0,
                                 );
-                                if let Some(term_arc) = &self.term {
-                                    if let Ok(term) = term_arc.lock() {
-                                        if let Some(link_id) = term.get_link_at(col, row) {
-                                            if let Some(url) = term.links.get(&link_id) {
-                                                opener::open(url).ok();
-                                                return;
-                                            }
-                                        }
-                                    }
+                                if let Some(term_arc) = &self.term
+                                    && let Ok(term) = term_arc.lock()
+                                    && let Some(link_id) = term.get_link_at(col, row)
+                                    && let Some(url) = term.link_target(link_id)
+                                {
+                                    opener::open(url).ok();
+                                    return;
                                 }
                             }
 
-                            self.is_mouse_dragging = true;
-
-                            self.selection_start = Some(renderer.pixels_to_grid(
+                            let (col, row) = renderer.pixels_to_grid(
                                 renderer.last_mouse_pos,
                                 #[cfg(target_os = "macos")]
                                 self.top_padding,
                                 #[cfg(not(target_os = "macos"))]
                                 0.0,
-                            ));
-                            self.selection_end = self.selection_start;
+                            );
 
                             if let Some(term_arc) = &self.term {
-                                term_arc.lock().unwrap().is_dirty = true;
+                                let mut term = term_arc.lock().unwrap();
+                                let point = term.point_at(col, row);
+
+                                self.is_mouse_dragging = true;
+                                self.selection_start = Some(point);
+                                self.selection_end = self.selection_start;
+
+                                term.is_dirty = true;
                             }
                             renderer.window.request_redraw();
                         } else {
                             self.is_mouse_dragging = false;
 
-                            if let Some(text) = self.get_selected_text() {
-                                if let Some(clipboard) = &mut self.clipboard {
-                                    clipboard.set_text(text).ok();
+                            // Clicking a prompt without dragging selects the output
+                            // of its command, left for the copy shortcut to take
+                            let block = match (self.selection_start, &self.term) {
+                                (Some(point), Some(term_arc))
+                                    if self.selection_end == Some(point) =>
+                                {
+                                    let mut term = term_arc.lock().unwrap();
+                                    let output = term.output_of_prompt(point.line);
+                                    term.is_dirty |= output.is_some();
+                                    output
                                 }
+                                _ => None,
+                            };
+
+                            if let Some(output) = block {
+                                self.selection_start = Some(output.start);
+                                self.selection_end = Some(output.end);
+                                renderer.window.request_redraw();
+                            } else if let Some(text) = self.get_selected_text()
+                                && let Some(clipboard) = &mut self.clipboard
+                            {
+                                clipboard.set_text(text).ok();
                             }
                         }
                     } else if button == winit::event::MouseButton::Left
@@ -437,15 +573,12 @@ impl ApplicationHandler<CustomEvent> for App {
                             #[cfg(not(target_os = "macos"))]
                             0.0,
                         );
-                        if let Some(term_arc) = &self.term {
-                            if let Ok(term) = term_arc.lock() {
-                                if let Some(link_id) = term.get_link_at(col, row) {
-                                    if let Some(url) = term.links.get(&link_id) {
-                                        opener::open(url).ok();
-                                        return;
-                                    }
-                                }
-                            }
+                        if let Some(term_arc) = &self.term
+                            && let Ok(term) = term_arc.lock()
+                            && let Some(link_id) = term.get_link_at(col, row)
+                            && let Some(url) = term.link_target(link_id)
+                        {
+                            opener::open(url).ok();
                         }
                     }
                 }
@@ -464,130 +597,240 @@ impl ApplicationHandler<CustomEvent> for App {
                         0.0,
                     );
 
-                    if self.is_mouse_dragging {
-                        self.selection_end = Some(renderer.pixels_to_grid(
-                            renderer.last_mouse_pos,
+                    let cell = renderer.pixels_to_grid(
+                        renderer.last_mouse_pos,
+                        #[cfg(target_os = "macos")]
+                        self.top_padding,
+                        #[cfg(not(target_os = "macos"))]
+                        0.0,
+                    );
+                    if self.last_reported_cell != Some(cell)
+                        && report_mouse(
+                            &self.term,
+                            &mut self.pty,
+                            renderer,
+                            self.modifiers,
+                            MouseAction::Motion,
+                            self.mouse_button_held,
                             #[cfg(target_os = "macos")]
                             self.top_padding,
                             #[cfg(not(target_os = "macos"))]
                             0.0,
-                        ));
+                        )
+                    {
+                        self.last_reported_cell = Some(cell);
+                        return;
+                    }
 
-                        if let Some(term_arc) = &self.term {
-                            term_arc.lock().unwrap().is_dirty = true;
+                    if self.is_mouse_dragging
+                        && let Some(term_arc) = &self.term
+                    {
+                        #[cfg(target_os = "macos")]
+                        let top_padding = self.top_padding;
+                        #[cfg(not(target_os = "macos"))]
+                        let top_padding = 0.0;
+
+                        let mut term = term_arc.lock().unwrap();
+
+                        // Dragging past the top or bottom edge scrolls,
+                        // so the selection can reach beyond the screen
+                        let (col, row) = cell;
+                        if renderer.last_mouse_pos.1 < top_padding {
+                            term.scroll_viewport(-1);
+                        } else if row >= renderer.grid_size(top_padding).1 {
+                            term.scroll_viewport(1);
                         }
 
+                        self.selection_end = Some(term.point_at(col, row));
+                        term.is_dirty = true;
+
                         renderer.window.request_redraw();
                     }
                 }
                 WindowEvent::MouseWheel { delta, .. } => {
-                    if let Some(term_arc) = &self.term {
-                        if let Ok(mut term) = term_arc.lock() {
-                            let scroll_lines = match delta {
-                                MouseScrollDelta::LineDelta(_, y) => y as i32,
-                                MouseScrollDelta::PixelDelta(pos) => (pos.y / 16.0) as i32,
-                            };
+                    let (scroll_x, scroll_y) = match delta {
+                        MouseScrollDelta::LineDelta(x, y) => (x as i32, y as i32),
+                        MouseScrollDelta::PixelDelta(pos) => {
+                            ((pos.x / 16.0) as i32, (pos.y / 16.0) as i32)
+                        }
+                    };
+
+                    // One wheel report per line scrolled
+                    let wheel_events = [
+                        (scroll_y, MouseButton::WheelUp, MouseButton::WheelDown),
+                        (scroll_x, MouseButton::WheelLeft, MouseButton::WheelRight),
+                    ];
+                    let mut reported = false;
+                    for (amount, positive, negative) in wheel_events {
+                        let button = if amount > 0 { positive } else { negative };
+                        for _ in 0..amount.unsigned_abs() {
+                            reported |= report_mouse(
+                                &self.term,
+                                &mut self.pty,
+                                renderer,
+                                self.modifiers,
+                                MouseAction::Press,
+                                Some(button),
+                                #[cfg(target_os = "macos")]
+                                self.top_padding,
+                                #[cfg(not(target_os = "macos"))]
+                                0.0,
+                            );
+                        }
+                    }
+                    if reported {
+                        return;
+                    }
 
-                            term.scroll_viewport(-scroll_lines);
+                    if let Some(term_arc) = &self.term
+                        && let Ok(mut term) = term_arc.lock()
+                    {
+                        let scroll_lines = match delta {
+                            MouseScrollDelta::LineDelta(_, y) => y as i32,
+                            MouseScrollDelta::PixelDelta(pos) => (pos.y / 16.0) as i32,
+                        };
 
-                            if let Some(renderer) = &self.renderer {
-                                renderer.window.request_redraw();
-                            }
+                        term.scroll_viewport(-scroll_lines);
+
+                        if let Some(renderer) = &self.renderer {
+                            renderer.window.request_redraw();
                         }
                     }
                 }
                 WindowEvent::KeyboardInput { event, .. } => {
                     use std::io::Write;
-                    use winit::keyboard::{Key, KeyCode, PhysicalKey};
+                    use winit::keyboard::{KeyCode, PhysicalKey};
+
+                    // Releases only reach programs using the kitty keyboard protocol
+                    if event.state == winit::event::ElementState::Released {
+                        if self.search.is_none()
+                            && let Some(term_arc) = &self.term
+                            && let Some(bytes) =
+                                term_arc.lock().unwrap().encode_key(&event, self.modifiers)
+                            && let Some(pty) = &mut self.pty
+                        {
+                            let _ = pty.writer.write_all(&bytes);
+                        }
+                        return;
+                    }
 
-                    if event.state == winit::event::ElementState::Pressed {
-                        let mut text_to_send: Option<String> = None;
+                    let mut bytes_to_send: Option<Vec<u8>> = None;
 
-                        #[cfg(target_os = "macos")]
-                        let is_shortcut_modifier = self.modifiers.super_key();
+                    #[cfg(target_os = "macos")]
+                    let is_shortcut_modifier = self.modifiers.super_key();
 
-                        #[cfg(not(target_os = "macos"))]
-                        let is_shortcut_modifier =
-                            self.modifiers.control_key() && self.modifiers.shift_key();
-
-                        // Check for shortcut modifier
-                        if is_shortcut_modifier {
-                            if let PhysicalKey::Code(key_code) = event.physical_key {
-                                match key_code {
-                                    KeyCode::KeyC => {
-                                        if let Some(text) = self.get_selected_text() {
-                                            if let Some(clipboard) = &mut self.clipboard {
-                                                clipboard.set_text(text).ok();
-                                            }
-                                        }
-
-                                        return;
-                                    }
-                                    KeyCode::KeyV => {
-                                        if let Some(clipboard) = &mut self.clipboard {
-                                            if let Ok(text) = clipboard.get_text() {
-                                                text_to_send = Some(text);
-                                            }
-                                        }
-                                    }
-                                    _ => {}
+                    #[cfg(not(target_os = "macos"))]
+                    let is_shortcut_modifier =
+                        self.modifiers.control_key() && self.modifiers.shift_key();
+
+                    if is_shortcut_modifier
+                        && event.physical_key == PhysicalKey::Code(KeyCode::KeyF)
+                    {
+                        self.search.get_or_insert_default();
+                        if let Some(term_arc) = &self.term {
+                            term_arc.lock().unwrap().is_dirty = true;
+                        }
+                        renderer.window.request_redraw();
+                        return;
+                    }
+
+                    // The search bar takes all other keys while it's open
+                    if self.search.is_some() {
+                        self.handle_search_key(&event);
+                        return;
+                    }
+
+                    // Check for shortcut modifier
+                    if is_shortcut_modifier && let PhysicalKey::Code(key_code) = event.physical_key
+                    {
+                        match key_code {
+                            KeyCode::KeyC => {
+                                if let Some(text) = self.get_selected_text()
+                                    && let Some(clipboard) = &mut self.clipboard
+                                {
+                                    clipboard.set_text(text).ok();
                                 }
+
+                                return;
                             }
-                        }
-                        // Handle Ctrl by itself
-                        else if self.modifiers.control_key() {
-                            if let Key::Character(s) = &event.logical_key {
-                                let s_lower = s.to_lowercase();
-                                if let Some(ch) = s_lower.chars().next() {
-                                    if ('a'..='z').contains(&ch) {
-                                        let ctrl_code = (ch as u8 - b'a' + 1) as char;
-                                        text_to_send = Some(ctrl_code.to_string());
-                                    }
+                            KeyCode::KeyV => {
+                                if let Some(clipboard) = &mut self.clipboard
+                                    && let Ok(text) = clipboard.get_text()
+                                    && let Some(term_arc) = &self.term
+                                {
+                                    let term = term_arc.lock().unwrap();
+                                    bytes_to_send = Some(term.format_paste(&text).into_bytes());
+                                } else {
+                                    return;
                                 }
                             }
-                        }
-
-                        // If no modifier combo, check for other special keys
-                        if text_to_send.is_none() {
-                            if let PhysicalKey::Code(key_code) = event.physical_key {
-                                let special_text = match key_code {
-                                    KeyCode::Enter => "\r",
-                                    KeyCode::Backspace => "\x7F",
-                                    KeyCode::Escape => "\x1b",
-                                    KeyCode::Tab => {
-                                        if self.modifiers.shift_key() {
-                                            // If shift is held, send the "back-tab" escape sequence
-                                            "\x1b[Z"
-                                        } else {
-                                            // Otherwise, send a normal tab
-                                            "\t"
-                                        }
-                                    }
-                                    KeyCode::ArrowUp => "\x1b[A",
-                                    KeyCode::ArrowDown => "\x1b[B",
-                                    KeyCode::ArrowRight => "\x1b[C",
-                                    KeyCode::ArrowLeft => "\x1b[D",
-                                    _ => "", // Unhandled special key
+                            // Copy the output of the last command
+                            KeyCode::KeyO => {
+                                if let Some(term_arc) = &self.term
+                                    && let Some(text) = term_arc.lock().unwrap().last_output()
+                                    && let Some(clipboard) = &mut self.clipboard
+                                {
+                                    clipboard.set_text(text).ok();
+                                }
+                                return;
+                            }
+                            // Fold or unfold the command output under the mouse
+                            KeyCode::KeyZ => {
+                                let (col, row) = renderer.pixels_to_grid(
+                                    renderer.last_mouse_pos,
+                                    #[cfg(target_os = "macos")]
+                                    self.top_padding,
+                                    #[cfg(not(target_os = "macos"))]
+                                    0.0,
+                                );
+                                if let Some(term_arc) = &self.term {
+                                    let mut term = term_arc.lock().unwrap();
+                                    let line = term.point_at(col, row).line;
+                                    term.toggle_fold(line);
+                                }
+                                renderer.window.request_redraw();
+                                return;
+                            }
+                            // Reload the config file, applying its colors to everything
+                            // on screen and in history
+                            KeyCode::KeyR => {
+                                self.reload_config();
+                                if let Some(renderer) = &self.renderer {
+                                    renderer.window.request_redraw();
+                                }
+                                return;
+                            }
+                            // Jump between prompts marked with OSC 133
+                            KeyCode::ArrowUp | KeyCode::ArrowDown => {
+                                let direction = if key_code == KeyCode::ArrowUp {
+                                    Direction::Backward
+                                } else {
+                                    Direction::Forward
                                 };
-                                if !special_text.is_empty() {
-                                    text_to_send = Some(special_text.to_string());
+                                if let Some(term_arc) = &self.term {
+                                    term_arc.lock().unwrap().scroll_to_prompt(direction);
                                 }
+                                renderer.window.request_redraw();
+                                return;
                             }
+                            _ => {}
                         }
+                    }
 
-                        // If still nothing, fall back to the text event from winit
-                        if text_to_send.is_none() {
-                            text_to_send = event.text.map(|t| t.to_string());
-                        }
+                    // Everything else goes through the key encoder
+                    if bytes_to_send.is_none()
+                        && let Some(term_arc) = &self.term
+                    {
+                        let term = term_arc.lock().unwrap();
+                        bytes_to_send = term.encode_key(&event, self.modifiers);
+                    }
 
-                        // Send the final result to the PTY
-                        if let Some(text) = text_to_send {
-                            if !text.is_empty() {
-                                if let Some(pty) = &mut self.pty {
-                                    let _ = pty.writer.write_all(text.as_bytes());
-                                }
-                            }
-                        }
+                    // Send the final result to the PTY
+                    if let Some(bytes) = bytes_to_send
+                        && !bytes.is_empty()
+                        && let Some(pty) = &mut self.pty
+                    {
+                        let _ = pty.writer.write_all(&bytes);
                     }
                 }
                 _ => (),
@@ -623,20 +866,66 @@ impl ApplicationHandler<CustomEvent> for App {
 
     fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
         // Check if reader thread has finished
-        if let Some(handle) = &self.reader {
-            if handle.is_finished() {
-                println!("PTY reader thread finished. Exiting");
-
-                if let Some(h) = self.reader.take() {
-                    let _ = h.join();
-                }
+        if let Some(handle) = &self.reader
+            && handle.is_finished()
+        {
+            println!("PTY reader thread finished. Exiting");
 
-                event_loop.exit();
+            if let Some(h) = self.reader.take() {
+                let _ = h.join();
             }
+
+            event_loop.exit();
         }
     }
 }
 
+/// Send a mouse event to the program if it turned on mouse reporting.
+/// Returns `false` if the event should be handled locally instead.
+/// Holding Shift always keeps the mouse local, so selection still works
+#[allow(clippy::too_many_arguments)]
+fn report_mouse(
+    term: &Option<Arc<Mutex<TerminalState>>>,
+    pty: &mut Option<PtyHandles>,
+    renderer: &Renderer,
+    modifiers: ModifiersState,
+    action: MouseAction,
+    button: Option<MouseButton>,
+    top_padding: f32,
+) -> bool {
+    use std::io::Write;
+
+    if modifiers.shift_key() {
+        return false;
+    }
+
+    let Some(term) = term.as_ref().and_then(|term_arc| term_arc.lock().ok()) else {
+        return false;
+    };
+    if !term.mouse.is_active() {
+        return false;
+    }
+
+    let (col, row) = renderer.pixels_to_grid(renderer.last_mouse_pos, top_padding);
+    let (x, y) = renderer.last_mouse_pos;
+    let event = MouseEvent {
+        action,
+        button,
+        col,
+        row: row.min(term.grid().rows.saturating_sub(1)),
+        pixels: (x.max(0.0) as usize, (y - top_padding).max(0.0) as usize),
+        modifiers,
+    };
+
+    if let Some(bytes) = term.mouse.encode(&event)
+        && let Some(pty) = pty
+    {
+        let _ = pty.writer.write_all(&bytes);
+    }
+
+    true
+}
+
 fn update_hover_state(
     term: &Option<Arc<Mutex<TerminalState>>>,
     hovered_link_id: &mut Option<u32>,
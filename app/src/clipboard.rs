@@ -0,0 +1,153 @@
+use arboard::Clipboard;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+
+use crate::config::ClipboardPolicy;
+
+/// Which system selection an OSC 52 request targets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClipboardKind {
+    Clipboard,
+    /// The X11 / Wayland primary selection. Other platforms don't have one
+    Primary,
+}
+
+/// Clipboard access asked for by the program with OSC 52.
+/// `TerminalState` queues these and `App` carries them out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClipboardRequest {
+    Store(ClipboardKind, String),
+    /// Send the contents back as `OSC 52 ; target ; base64 data`, ending with `terminator`
+    Load {
+        kind: ClipboardKind,
+        target: char,
+        terminator: &'static str,
+    },
+}
+
+impl ClipboardRequest {
+    /// Parse `OSC 52 ; targets ; data` into a request `policy` allows
+    pub fn from_osc(
+        params: &[&[u8]],
+        policy: &ClipboardPolicy,
+        terminator: &'static str,
+    ) -> Option<Self> {
+        let targets = params.get(1)?;
+        let data = params.get(2)?;
+
+        // `c` is the clipboard and `p` the primary selection. `s` is xterm's
+        // configurable selection, which we treat as the clipboard, as we do
+        // an empty target list
+        let (target, kind) = targets
+            .iter()
+            .find_map(|&t| match t {
+                b'c' | b's' => Some((t as char, ClipboardKind::Clipboard)),
+                b'p' => Some(('p', ClipboardKind::Primary)),
+                _ => None,
+            })
+            .unwrap_or(('c', ClipboardKind::Clipboard));
+
+        if *data == b"?" {
+            if !policy.allow_read {
+                log::debug!("OSC 52: clipboard read denied by config");
+                return None;
+            }
+
+            return Some(Self::Load {
+                kind,
+                target,
+                terminator,
+            });
+        }
+
+        if !policy.allow_write {
+            log::debug!("OSC 52: clipboard write denied by config");
+            return None;
+        }
+
+        // Base64 turns every 3 bytes into 4, so this rejects oversized
+        // payloads before decoding them
+        if data.len() / 4 * 3 > policy.max_bytes + 2 {
+            log::debug!("OSC 52: payload of {} bytes is over the limit", data.len());
+            return None;
+        }
+
+        let decoded = BASE64.decode(data).ok()?;
+        if decoded.len() > policy.max_bytes {
+            return None;
+        }
+
+        Some(Self::Store(
+            kind,
+            String::from_utf8_lossy(&decoded).into_owned(),
+        ))
+    }
+
+    /// Carry out the request. Returns the reply for the PTY, if there is one
+    pub fn execute(self, clipboard: &mut Clipboard) -> Option<Vec<u8>> {
+        match self {
+            Self::Store(kind, text) => {
+                if let Err(e) = set_text(clipboard, kind, text) {
+                    log::warn!("OSC 52: failed to set clipboard: {e}");
+                }
+                None
+            }
+            Self::Load {
+                kind,
+                target,
+                terminator,
+            } => {
+                // An empty clipboard is answered with empty data
+                let text = get_text(clipboard, kind).unwrap_or_default();
+                let data = BASE64.encode(text);
+                Some(format!("\x1b]52;{target};{data}{terminator}").into_bytes())
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_text(
+    clipboard: &mut Clipboard,
+    kind: ClipboardKind,
+    text: String,
+) -> Result<(), arboard::Error> {
+    use arboard::{LinuxClipboardKind, SetExtLinux};
+
+    let selection = match kind {
+        ClipboardKind::Clipboard => LinuxClipboardKind::Clipboard,
+        ClipboardKind::Primary => LinuxClipboardKind::Primary,
+    };
+    clipboard.set().clipboard(selection).text(text)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_text(
+    clipboard: &mut Clipboard,
+    kind: ClipboardKind,
+    text: String,
+) -> Result<(), arboard::Error> {
+    match kind {
+        ClipboardKind::Clipboard => clipboard.set_text(text),
+        ClipboardKind::Primary => Ok(()),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn get_text(clipboard: &mut Clipboard, kind: ClipboardKind) -> Option<String> {
+    use arboard::{GetExtLinux, LinuxClipboardKind};
+
+    let selection = match kind {
+        ClipboardKind::Clipboard => LinuxClipboardKind::Clipboard,
+        ClipboardKind::Primary => LinuxClipboardKind::Primary,
+    };
+    clipboard.get().clipboard(selection).text().ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn get_text(clipboard: &mut Clipboard, kind: ClipboardKind) -> Option<String> {
+    match kind {
+        ClipboardKind::Clipboard => clipboard.get_text().ok(),
+        ClipboardKind::Primary => None,
+    }
+}
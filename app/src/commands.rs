@@ -0,0 +1,94 @@
+use std::ops::Range;
+
+use screen_grid::{Point, PromptMark, ScreenGrid};
+
+/// A command run at a shell prompt, found from its OSC 133 marks.
+/// Only positions are kept; the text is read from the grid when needed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandRecord {
+    /// Where the prompt starts
+    pub prompt: Point,
+    /// Where the command line starts
+    pub command: Point,
+    /// Where the output starts and ends. Ends at the cursor while the command runs
+    pub output: Range<Point>,
+    /// Exit status, if the shell reported one
+    pub exit_code: Option<i32>,
+    pub finished: bool,
+}
+
+impl CommandRecord {
+    /// The command line as the user typed it
+    pub fn command_line(&self, grid: &ScreenGrid) -> String {
+        grid.text_in_range(self.command, self.output.start)
+            .trim()
+            .to_string()
+    }
+}
+
+/// Every command with a start of output mark in `grid`, oldest first
+pub fn command_records(grid: &ScreenGrid) -> Vec<CommandRecord> {
+    let cursor = Point {
+        line: grid.scrollback_len() + grid.cur_y,
+        col: grid.cur_x,
+    };
+
+    let mut records = Vec::new();
+    // Prompt and command line starts, until the command runs
+    let mut prompt: Option<(Point, Point)> = None;
+    let mut running: Option<CommandRecord> = None;
+
+    for (point, mark) in grid.marks() {
+        match mark {
+            PromptMark::PromptStart => {
+                // A new prompt ends a command whose shell didn't report the end
+                if let Some(mut record) = running.take() {
+                    record.output.end = point;
+                    records.push(record);
+                }
+                prompt = Some((point, point));
+            }
+            PromptMark::CommandStart => {
+                if let Some((_, command)) = &mut prompt {
+                    *command = point;
+                }
+            }
+            PromptMark::OutputStart => {
+                if let Some((prompt, command)) = prompt.take() {
+                    running = Some(CommandRecord {
+                        prompt,
+                        command,
+                        output: point..cursor.max(point),
+                        exit_code: None,
+                        finished: false,
+                    });
+                }
+            }
+            PromptMark::CommandEnd(exit_code) => {
+                if let Some(mut record) = running.take() {
+                    record.output.end = point;
+                    record.exit_code = exit_code;
+                    record.finished = true;
+                    records.push(record);
+                }
+            }
+        }
+    }
+
+    records.extend(running);
+    records
+}
+
+/// The prompt line and output lines of every command in `grid`, oldest first
+pub fn output_blocks(grid: &ScreenGrid) -> Vec<(usize, Range<usize>)> {
+    command_records(grid)
+        .into_iter()
+        .map(|record| (record.prompt.line, output_lines(&record.output)))
+        .collect()
+}
+
+/// Output ending at the start of a line doesn't cover that line
+fn output_lines(output: &Range<Point>) -> Range<usize> {
+    let end = output.end.line + usize::from(output.end.col > 0);
+    output.start.line..end.max(output.start.line)
+}
@@ -1,13 +1,145 @@
 use directories::ProjectDirs;
+use screen_grid::Rgb;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
+use crate::palette;
+
+/// Themes that ship with the terminal, by name. A `themes/<name>.toml`
+/// next to the config file takes precedence over these
+const BUNDLED_THEMES: &[(&str, &str)] = &[
+    (
+        "dracula",
+        include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../assets/themes/dracula.toml"
+        )),
+    ),
+    (
+        "gruvbox-dark",
+        include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../assets/themes/gruvbox-dark.toml"
+        )),
+    ),
+    (
+        "solarized-dark",
+        include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../assets/themes/solarized-dark.toml"
+        )),
+    ),
+    (
+        "solarized-light",
+        include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../assets/themes/solarized-light.toml"
+        )),
+    ),
+];
+
+/// A color in the config file: a hex string such as `"#c0c0c0"` (anything
+/// `palette::parse_color` takes), or an `[r, g, b]` array
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "ColorValue", into = "String")]
+pub struct ConfigColor(pub u8, pub u8, pub u8);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ColorValue {
+    Hex(String),
+    Array([u8; 3]),
+}
+
+impl TryFrom<ColorValue> for ConfigColor {
+    type Error = String;
+
+    fn try_from(value: ColorValue) -> Result<Self, Self::Error> {
+        match value {
+            ColorValue::Hex(spec) => palette::parse_color(&spec)
+                .map(|Rgb(r, g, b)| ConfigColor(r, g, b))
+                .ok_or_else(|| format!("invalid color `{spec}`")),
+            ColorValue::Array([r, g, b]) => Ok(ConfigColor(r, g, b)),
+        }
+    }
+}
+
+impl From<ConfigColor> for String {
+    fn from(ConfigColor(r, g, b): ConfigColor) -> Self {
+        format!("#{r:02x}{g:02x}{b:02x}")
+    }
+}
+
+impl From<ConfigColor> for Rgb {
+    fn from(ConfigColor(r, g, b): ConfigColor) -> Self {
+        Rgb(r, g, b)
+    }
+}
+
+/// Replaces one of the 256 indexed colors
 #[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct IndexedColor {
+    pub index: u8,
+    pub color: ConfigColor,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
 pub struct Colors {
-    pub foreground: (u8, u8, u8),
-    pub background: (u8, u8, u8),
-    pub cursor: (u8, u8, u8),
-    pub cursor_text: (u8, u8, u8),
+    pub foreground: ConfigColor,
+    pub background: ConfigColor,
+    pub cursor: ConfigColor,
+    pub cursor_text: ConfigColor,
+    /// Drawn translucent over selected text
+    pub selection: ConfigColor,
+    /// Search matches, drawn translucent like the selection
+    pub search_match: ConfigColor,
+    /// The match the search bar is on
+    pub search_current: ConfigColor,
+    /// The 16 ANSI colors: black, red, green, yellow, blue, magenta, cyan and
+    /// white, then their bright versions
+    pub palette: [ConfigColor; 16],
+    /// Overrides for the rest of the 256 colors, or any of the first 16
+    pub indexed: Vec<IndexedColor>,
+}
+
+impl Default for Colors {
+    fn default() -> Self {
+        let palette = palette::XTERM_16.map(|Rgb(r, g, b)| ConfigColor(r, g, b));
+
+        Self {
+            foreground: ConfigColor(0xC0, 0xC0, 0xC0),
+            background: ConfigColor(0x00, 0x00, 0x00),
+            cursor: ConfigColor(0xC0, 0xC0, 0xC0),
+            cursor_text: ConfigColor(0x00, 0x00, 0x00),
+            selection: ConfigColor(120, 120, 120),
+            search_match: ConfigColor(200, 160, 0),
+            search_current: ConfigColor(255, 140, 0),
+            palette,
+            indexed: Vec::new(),
+        }
+    }
+}
+
+/// What programs may do with the clipboard through OSC 52
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ClipboardPolicy {
+    pub allow_write: bool,
+    /// Off by default, since any program in the terminal could read what was copied
+    pub allow_read: bool,
+    /// Largest text a program may copy, in bytes
+    pub max_bytes: usize,
+}
+
+impl Default for ClipboardPolicy {
+    fn default() -> Self {
+        Self {
+            allow_write: true,
+            allow_read: false,
+            max_bytes: 1024 * 1024,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -15,8 +147,24 @@ pub struct Colors {
 pub struct Config {
     pub font_size: f32,
     pub shell: Vec<String>,
+    /// Name of a theme to take colors from: a file in the `themes` directory
+    /// next to the config file, or one of the bundled themes. Anything under
+    /// `[colors]` in the config file still overrides it
+    pub theme: Option<String>,
     pub colors: Colors,
     pub background_opacity: f32,
+    /// Memory budget for scrollback history, in bytes
+    pub scrollback_bytes: usize,
+    /// Keep history that doesn't fit in the budget in a temporary file instead of dropping it.
+    /// Prompts in that part of history aren't found by prompt jumps or command blocks
+    pub scrollback_unlimited: bool,
+    /// Window title. `{title}` is replaced with the title set by the program,
+    /// and `{icon_name}`, `{shell}`, `{cwd}`, `{cols}` and `{rows}` with what they name
+    pub title_template: String,
+    pub clipboard: ClipboardPolicy,
+    /// Show a notification when a command marked by shell integration (OSC 133)
+    /// runs longer than this many seconds while the window is unfocused. 0 turns it off
+    pub notify_after_seconds: u64,
     #[cfg(target_os = "macos")]
     pub macos_transparent_titlebar: bool,
 }
@@ -26,13 +174,14 @@ impl Default for Config {
         Self {
             font_size: 15.0,
             shell: vec!["bash".into(), "-i".into()],
-            colors: Colors {
-                foreground: (0xC0, 0xC0, 0xC0),
-                background: (0x00, 0x00, 0x00),
-                cursor: (0xC0, 0xC0, 0xC0),
-                cursor_text: (0x00, 0x00, 0x00),
-            },
+            theme: None,
+            colors: Colors::default(),
             background_opacity: 1.0,
+            scrollback_bytes: 16 * 1024 * 1024,
+            scrollback_unlimited: false,
+            title_template: "{title}".into(),
+            clipboard: ClipboardPolicy::default(),
+            notify_after_seconds: 0,
             #[cfg(target_os = "macos")]
             macos_transparent_titlebar: false,
         }
@@ -53,11 +202,50 @@ impl Config {
             PathBuf::from("config.toml")
         };
 
-        let s = config::Config::builder()
-            .add_source(config::Config::try_from(&Self::default())?)
-            .add_source(config::File::from(config_path).required(false))
-            .build()?;
+        Self::load_from(&config_path)
+    }
+
+    /// Load config from `config_path`, layered over the defaults and the theme it names
+    fn load_from(config_path: &Path) -> Result<Self, config::ConfigError> {
+        let build = |theme: Option<&str>| {
+            let mut builder =
+                config::Config::builder().add_source(config::Config::try_from(&Self::default())?);
+            if let Some(theme) = theme {
+                builder =
+                    builder.add_source(config::File::from_str(theme, config::FileFormat::Toml));
+            }
+
+            builder
+                .add_source(config::File::from(config_path).required(false))
+                .build()?
+                .try_deserialize::<Self>()
+        };
+
+        let config = build(None)?;
+        let Some(name) = &config.theme else {
+            return Ok(config);
+        };
+
+        let themes_dir = config_path.parent().unwrap_or(Path::new("")).join("themes");
+        match load_theme(&themes_dir, name) {
+            Some(theme) => build(Some(&theme)),
+            None => {
+                log::warn!("Theme `{name}` not found in {}", themes_dir.display());
+                Ok(config)
+            }
+        }
+    }
+}
 
-        s.try_deserialize()
+/// The contents of theme `name`: a `[colors]` table, like the one in the config file
+fn load_theme(themes_dir: &Path, name: &str) -> Option<String> {
+    let path = themes_dir.join(format!("{name}.toml"));
+    if let Ok(theme) = std::fs::read_to_string(&path) {
+        return Some(theme);
     }
+
+    BUNDLED_THEMES
+        .iter()
+        .find(|(bundled, _)| *bundled == name)
+        .map(|(_, theme)| theme.to_string())
 }
@@ -0,0 +1,68 @@
+use std::path::{Path, PathBuf};
+
+/// Parse an OSC 7 `file://host/path` URI into a local directory.
+/// Returns `None` if the URI is malformed or names another machine,
+/// as it does when the shell is running over ssh
+pub fn parse_file_uri(uri: &str) -> Option<PathBuf> {
+    let rest = uri.strip_prefix("file://")?;
+    let (host, path) = rest.split_at(rest.find('/')?);
+
+    if !is_local_host(host) {
+        return None;
+    }
+
+    let path = String::from_utf8(percent_decode(path)?).ok()?;
+
+    // `file:///C:/Users` names `C:/Users`
+    #[cfg(windows)]
+    let path = path.strip_prefix('/').unwrap_or(&path).to_string();
+
+    Some(PathBuf::from(path))
+}
+
+/// Replace `%XX` escapes with the bytes they stand for
+fn percent_decode(text: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(text.len());
+    let mut bytes = text.bytes();
+
+    while let Some(byte) = bytes.next() {
+        if byte == b'%' {
+            let hex = [bytes.next()?, bytes.next()?];
+            let hex = std::str::from_utf8(&hex).ok()?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+        } else {
+            out.push(byte);
+        }
+    }
+
+    Some(out)
+}
+
+fn is_local_host(host: &str) -> bool {
+    if host.is_empty() || host.eq_ignore_ascii_case("localhost") {
+        return true;
+    }
+
+    // Shells may report either the short or the fully qualified name
+    let local = gethostname::gethostname();
+    let local = local.to_string_lossy();
+    let short = |name: &str| name.split('.').next().unwrap_or_default().to_string();
+
+    host.eq_ignore_ascii_case(&local)
+        || (!host.contains('.') && host.eq_ignore_ascii_case(&short(&local)))
+        || (!local.contains('.') && local.eq_ignore_ascii_case(&short(host)))
+}
+
+/// `path` with the home directory shortened to `~`, for display
+pub fn display_path(path: &Path) -> String {
+    let home = directories::BaseDirs::new().map(|dirs| dirs.home_dir().to_path_buf());
+
+    match home
+        .as_deref()
+        .and_then(|home| path.strip_prefix(home).ok())
+    {
+        Some(rest) if rest.as_os_str().is_empty() => "~".to_string(),
+        Some(rest) => format!("~/{}", rest.display()),
+        None => path.display().to_string(),
+    }
+}
@@ -0,0 +1,629 @@
+use bitflags::bitflags;
+use winit::event::{ElementState, KeyEvent};
+use winit::keyboard::{Key, KeyCode, KeyLocation, ModifiersState, NamedKey, PhysicalKey};
+use winit::platform::modifier_supplement::KeyEventExtModifierSupplement;
+
+/// Modes the program can set that change what keys send
+#[derive(Debug, Clone, Copy, Default)]
+pub struct KeyModes {
+    /// DECCKM - Cursor keys send `SS3 X` instead of `CSI X`
+    pub app_cursor: bool,
+    /// DECKPAM / DECKPNM - Keypad keys send `SS3 X` instead of their text
+    pub app_keypad: bool,
+}
+
+bitflags! {
+    /// Kitty keyboard protocol enhancements requested by the program
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct KittyFlags: u8 {
+        /// Send keys that are ambiguous in legacy encoding as `CSI u`
+        const DISAMBIGUATE = 1;
+        /// Report repeats and releases, not just presses
+        const REPORT_EVENT_TYPES = 2;
+        /// Add the shifted and base layout keys
+        const REPORT_ALTERNATE_KEYS = 4;
+        /// Send every key as an escape code, even ones that produce text
+        const REPORT_ALL_KEYS = 8;
+        /// Add the text a key produces, with `REPORT_ALL_KEYS`
+        const REPORT_TEXT = 16;
+    }
+}
+
+/// Max entries `KittyStack::push` keeps. Pushing past this forgets the oldest
+const KITTY_STACK_LIMIT: usize = 16;
+
+/// Kitty keyboard flags for one screen, with the entries saved by pushes
+#[derive(Debug, Clone, Default)]
+pub struct KittyStack {
+    current: KittyFlags,
+    saved: Vec<KittyFlags>,
+}
+
+impl KittyStack {
+    pub fn flags(&self) -> KittyFlags {
+        self.current
+    }
+
+    /// `CSI > flags u` - Save the current flags and switch to `flags`
+    pub fn push(&mut self, flags: KittyFlags) {
+        if self.saved.len() == KITTY_STACK_LIMIT {
+            self.saved.remove(0);
+        }
+        self.saved.push(self.current);
+        self.current = flags;
+    }
+
+    /// `CSI < n u` - Go back `n` entries. Popping everything clears the flags
+    pub fn pop(&mut self, n: usize) {
+        for _ in 0..n {
+            self.current = self.saved.pop().unwrap_or_default();
+        }
+    }
+
+    /// `CSI = flags ; mode u` - Replace the current flags (mode 1),
+    /// add to them (mode 2) or remove from them (mode 3)
+    pub fn set(&mut self, flags: KittyFlags, mode: usize) {
+        match mode {
+            1 => self.current = flags,
+            2 => self.current |= flags,
+            3 => self.current &= !flags,
+            _ => {}
+        }
+    }
+}
+
+/// How a special key is written when it isn't sent as plain text
+enum Sequence {
+    /// `CSI X`, or `SS3 X` in application cursor mode. `CSI 1;<mod> X` when modified
+    Cursor(u8),
+    /// `SS3 X`. `CSI 1;<mod> X` when modified
+    Ss3(u8),
+    /// `CSI n ~`. `CSI n;<mod> ~` when modified
+    Tilde(u8),
+}
+
+impl KeyModes {
+    /// Encode a key event for the PTY, or `None` if it sends nothing.
+    /// Keys are encoded the way xterm does, unless the program enabled kitty
+    /// keyboard protocol `flags`
+    pub fn encode(
+        &self,
+        event: &KeyEvent,
+        modifiers: ModifiersState,
+        flags: KittyFlags,
+    ) -> Option<Vec<u8>> {
+        let pressed = event.state == ElementState::Pressed;
+
+        if flags.is_empty() {
+            return pressed.then(|| self.encode_legacy(event, modifiers))?;
+        }
+
+        let Some(key) = kitty_key(event) else {
+            return pressed.then(|| self.encode_legacy(event, modifiers))?;
+        };
+
+        let modifiers = match key {
+            KittyKey::Modifier(_) => own_modifier(event, modifiers),
+            _ => modifiers,
+        };
+        let mods = kitty_modifier_param(modifiers);
+        let all_keys = flags.contains(KittyFlags::REPORT_ALL_KEYS);
+        let disambiguate = flags.contains(KittyFlags::DISAMBIGUATE);
+
+        let escaped = all_keys
+            || match key {
+                KittyKey::Text(_) => {
+                    disambiguate
+                        && (modifiers.control_key() || modifiers.alt_key() || modifiers.super_key())
+                }
+                KittyKey::Control(_) => disambiguate && mods > 1,
+                KittyKey::Escape | KittyKey::Private(_) => disambiguate,
+                KittyKey::Functional(..) => true,
+                KittyKey::Modifier(_) => false,
+            };
+
+        if !escaped {
+            return pressed.then(|| self.encode_legacy(event, modifiers))?;
+        }
+
+        // Enter, Tab and Backspace only report releases along with all other keys
+        if !pressed
+            && (!flags.contains(KittyFlags::REPORT_EVENT_TYPES)
+                || matches!(key, KittyKey::Control(_)) && !all_keys)
+        {
+            return None;
+        }
+
+        let event_type = if !flags.contains(KittyFlags::REPORT_EVENT_TYPES) {
+            1
+        } else if !pressed {
+            3
+        } else if event.repeat {
+            2
+        } else {
+            1
+        };
+
+        let text: Vec<String> = match &event.text {
+            Some(text) if pressed && all_keys && flags.contains(KittyFlags::REPORT_TEXT) => text
+                .chars()
+                .filter(|c| !c.is_control())
+                .map(|c| u32::from(c).to_string())
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        let (number, final_byte) = match key {
+            KittyKey::Text(code)
+            | KittyKey::Control(code)
+            | KittyKey::Private(code)
+            | KittyKey::Modifier(code) => (code, b'u'),
+            KittyKey::Escape => (27, b'u'),
+            KittyKey::Functional(number, final_byte) => (number, final_byte),
+        };
+
+        let plain = mods == 1 && event_type == 1 && text.is_empty();
+
+        // Unmodified cursor and function keys keep their legacy form, so DECCKM still applies
+        if plain && !all_keys && matches!(key, KittyKey::Functional(..)) {
+            return self.encode_legacy(event, modifiers);
+        }
+
+        let mut out = String::from("\x1b[");
+
+        if !(plain && final_byte != b'u' && final_byte != b'~') {
+            out += &number.to_string();
+        }
+
+        if let KittyKey::Text(code) = key
+            && flags.contains(KittyFlags::REPORT_ALTERNATE_KEYS)
+        {
+            let shifted = match &event.logical_key {
+                Key::Character(s) if modifiers.shift_key() => single_char(s),
+                _ => None,
+            }
+            .filter(|&c| u32::from(c) != code);
+
+            let base = match event.physical_key {
+                PhysicalKey::Code(code) => us_layout_char(code),
+                _ => None,
+            }
+            .filter(|&c| u32::from(c) != code);
+
+            if shifted.is_some() || base.is_some() {
+                out += ":";
+                if let Some(shifted) = shifted {
+                    out += &u32::from(shifted).to_string();
+                }
+            }
+            if let Some(base) = base {
+                out += &format!(":{}", u32::from(base));
+            }
+        }
+
+        if !plain {
+            out += &format!(";{mods}");
+            if event_type != 1 {
+                out += &format!(":{event_type}");
+            }
+        }
+
+        if !text.is_empty() {
+            out += &format!(";{}", text.join(":"));
+        }
+
+        out.push(final_byte as char);
+        Some(out.into_bytes())
+    }
+
+    /// Encode a key press the way xterm does
+    fn encode_legacy(&self, event: &KeyEvent, modifiers: ModifiersState) -> Option<Vec<u8>> {
+        let alt = modifiers.alt_key();
+        let ctrl = modifiers.control_key();
+        let shift = modifiers.shift_key();
+
+        // Only while Num Lock is on, otherwise these are cursor and editing keys
+        if self.app_keypad
+            && event.location == KeyLocation::Numpad
+            && event.text.is_some()
+            && let PhysicalKey::Code(code) = event.physical_key
+            && let Some(final_byte) = keypad_final(code)
+        {
+            return Some(vec![0x1b, b'O', final_byte]);
+        }
+
+        if let Key::Named(named) = &event.logical_key {
+            if let Some(sequence) = named_sequence(*named) {
+                return Some(self.write_sequence(sequence, modifier_param(modifiers)));
+            }
+
+            let bytes: &[u8] = match named {
+                NamedKey::Enter => b"\r",
+                NamedKey::Tab if shift => b"\x1b[Z",
+                NamedKey::Tab => b"\t",
+                NamedKey::Backspace if ctrl => b"\x08",
+                NamedKey::Backspace => b"\x7f",
+                NamedKey::Escape => b"\x1b",
+                NamedKey::Space if ctrl => b"\0",
+                NamedKey::Space => b" ",
+                _ => return event.text.as_ref().map(|t| t.as_bytes().to_vec()),
+            };
+
+            return Some(with_alt(bytes, alt));
+        }
+
+        if let Key::Character(s) = &event.logical_key {
+            if ctrl
+                && let Some(c) = single_char(s)
+                && let Some(code) = control_code(c)
+            {
+                return Some(with_alt(&[code], alt));
+            }
+
+            // Alt as Meta: the key's character, prefixed with ESC
+            if alt {
+                return Some(with_alt(s.as_bytes(), true));
+            }
+        }
+
+        event.text.as_ref().map(|t| t.as_bytes().to_vec())
+    }
+
+    fn write_sequence(&self, sequence: Sequence, modifier: u8) -> Vec<u8> {
+        let modified = modifier > 1;
+
+        let text = match sequence {
+            Sequence::Cursor(x) | Sequence::Ss3(x) if modified => {
+                format!("\x1b[1;{modifier}{}", x as char)
+            }
+            Sequence::Cursor(x) if !self.app_cursor => format!("\x1b[{}", x as char),
+            Sequence::Cursor(x) | Sequence::Ss3(x) => format!("\x1bO{}", x as char),
+            Sequence::Tilde(n) if modified => format!("\x1b[{n};{modifier}~"),
+            Sequence::Tilde(n) => format!("\x1b[{n}~"),
+        };
+
+        text.into_bytes()
+    }
+}
+
+/// How the kitty keyboard protocol treats a key
+#[derive(Clone, Copy)]
+enum KittyKey {
+    /// A key that produces text, by its unshifted code point
+    Text(u32),
+    /// Enter, Tab and Backspace, which keep their legacy bytes when unmodified
+    Control(u32),
+    Escape,
+    /// A key with a legacy form, `CSI 1;<mod> X` or `CSI n;<mod> ~`
+    Functional(u32, u8),
+    /// A key that sends nothing in legacy encoding, such as the keypad or F13
+    Private(u32),
+    /// Modifier and lock keys, only reported along with all other keys
+    Modifier(u32),
+}
+
+fn kitty_key(event: &KeyEvent) -> Option<KittyKey> {
+    if let Some(code) = kitty_keypad_code(event) {
+        return Some(KittyKey::Private(code));
+    }
+
+    let Key::Named(named) = &event.logical_key else {
+        let Key::Character(s) = event.key_without_modifiers() else {
+            return None;
+        };
+        let c = single_char(&s)?.to_lowercase().next()?;
+        return Some(KittyKey::Text(c.into()));
+    };
+
+    let right = event.location == KeyLocation::Right;
+    let side = |left: u32| if right { left + 6 } else { left };
+
+    let key = match named {
+        NamedKey::Space => KittyKey::Text(32),
+        NamedKey::Enter => KittyKey::Control(13),
+        NamedKey::Tab => KittyKey::Control(9),
+        NamedKey::Backspace => KittyKey::Control(127),
+        NamedKey::Escape => KittyKey::Escape,
+        NamedKey::Insert => KittyKey::Functional(2, b'~'),
+        NamedKey::Delete => KittyKey::Functional(3, b'~'),
+        NamedKey::PageUp => KittyKey::Functional(5, b'~'),
+        NamedKey::PageDown => KittyKey::Functional(6, b'~'),
+        NamedKey::ArrowUp => KittyKey::Functional(1, b'A'),
+        NamedKey::ArrowDown => KittyKey::Functional(1, b'B'),
+        NamedKey::ArrowRight => KittyKey::Functional(1, b'C'),
+        NamedKey::ArrowLeft => KittyKey::Functional(1, b'D'),
+        NamedKey::Home => KittyKey::Functional(1, b'H'),
+        NamedKey::End => KittyKey::Functional(1, b'F'),
+        NamedKey::F1 => KittyKey::Functional(1, b'P'),
+        NamedKey::F2 => KittyKey::Functional(1, b'Q'),
+        // Not `CSI 1;<mod> R`, which would look like a cursor position report
+        NamedKey::F3 => KittyKey::Functional(13, b'~'),
+        NamedKey::F4 => KittyKey::Functional(1, b'S'),
+        NamedKey::F5 => KittyKey::Functional(15, b'~'),
+        NamedKey::F6 => KittyKey::Functional(17, b'~'),
+        NamedKey::F7 => KittyKey::Functional(18, b'~'),
+        NamedKey::F8 => KittyKey::Functional(19, b'~'),
+        NamedKey::F9 => KittyKey::Functional(20, b'~'),
+        NamedKey::F10 => KittyKey::Functional(21, b'~'),
+        NamedKey::F11 => KittyKey::Functional(23, b'~'),
+        NamedKey::F12 => KittyKey::Functional(24, b'~'),
+        NamedKey::F13 => KittyKey::Private(57376),
+        NamedKey::F14 => KittyKey::Private(57377),
+        NamedKey::F15 => KittyKey::Private(57378),
+        NamedKey::F16 => KittyKey::Private(57379),
+        NamedKey::F17 => KittyKey::Private(57380),
+        NamedKey::F18 => KittyKey::Private(57381),
+        NamedKey::F19 => KittyKey::Private(57382),
+        NamedKey::F20 => KittyKey::Private(57383),
+        NamedKey::F21 => KittyKey::Private(57384),
+        NamedKey::F22 => KittyKey::Private(57385),
+        NamedKey::F23 => KittyKey::Private(57386),
+        NamedKey::F24 => KittyKey::Private(57387),
+        NamedKey::F25 => KittyKey::Private(57388),
+        NamedKey::F26 => KittyKey::Private(57389),
+        NamedKey::F27 => KittyKey::Private(57390),
+        NamedKey::F28 => KittyKey::Private(57391),
+        NamedKey::F29 => KittyKey::Private(57392),
+        NamedKey::F30 => KittyKey::Private(57393),
+        NamedKey::F31 => KittyKey::Private(57394),
+        NamedKey::F32 => KittyKey::Private(57395),
+        NamedKey::F33 => KittyKey::Private(57396),
+        NamedKey::F34 => KittyKey::Private(57397),
+        NamedKey::F35 => KittyKey::Private(57398),
+        NamedKey::PrintScreen => KittyKey::Private(57361),
+        NamedKey::Pause => KittyKey::Private(57362),
+        NamedKey::ContextMenu => KittyKey::Private(57363),
+        NamedKey::MediaPlay => KittyKey::Private(57428),
+        NamedKey::MediaPause => KittyKey::Private(57429),
+        NamedKey::MediaPlayPause => KittyKey::Private(57430),
+        NamedKey::MediaStop => KittyKey::Private(57432),
+        NamedKey::MediaFastForward => KittyKey::Private(57433),
+        NamedKey::MediaRewind => KittyKey::Private(57434),
+        NamedKey::MediaTrackNext => KittyKey::Private(57435),
+        NamedKey::MediaTrackPrevious => KittyKey::Private(57436),
+        NamedKey::MediaRecord => KittyKey::Private(57437),
+        NamedKey::AudioVolumeDown => KittyKey::Private(57438),
+        NamedKey::AudioVolumeUp => KittyKey::Private(57439),
+        NamedKey::AudioVolumeMute => KittyKey::Private(57440),
+        NamedKey::CapsLock => KittyKey::Modifier(57358),
+        NamedKey::ScrollLock => KittyKey::Modifier(57359),
+        NamedKey::NumLock => KittyKey::Modifier(57360),
+        NamedKey::Shift => KittyKey::Modifier(side(57441)),
+        NamedKey::Control => KittyKey::Modifier(side(57442)),
+        NamedKey::Alt => KittyKey::Modifier(side(57443)),
+        NamedKey::Super => KittyKey::Modifier(side(57444)),
+        NamedKey::Hyper => KittyKey::Modifier(side(57445)),
+        NamedKey::Meta => KittyKey::Modifier(side(57446)),
+        NamedKey::AltGraph => KittyKey::Modifier(57453),
+        _ => return None,
+    };
+
+    Some(key)
+}
+
+/// Keypad keys have their own codes, whether or not Num Lock is on
+fn kitty_keypad_code(event: &KeyEvent) -> Option<u32> {
+    if event.location != KeyLocation::Numpad {
+        return None;
+    }
+
+    if let Key::Named(named) = &event.logical_key {
+        let code = match named {
+            NamedKey::Enter => 57414,
+            NamedKey::ArrowLeft => 57417,
+            NamedKey::ArrowRight => 57418,
+            NamedKey::ArrowUp => 57419,
+            NamedKey::ArrowDown => 57420,
+            NamedKey::PageUp => 57421,
+            NamedKey::PageDown => 57422,
+            NamedKey::Home => 57423,
+            NamedKey::End => 57424,
+            NamedKey::Insert => 57425,
+            NamedKey::Delete => 57426,
+            NamedKey::Clear => 57427,
+            _ => return None,
+        };
+        return Some(code);
+    }
+
+    let PhysicalKey::Code(code) = event.physical_key else {
+        return None;
+    };
+
+    let code = match code {
+        KeyCode::Numpad0 => 57399,
+        KeyCode::Numpad1 => 57400,
+        KeyCode::Numpad2 => 57401,
+        KeyCode::Numpad3 => 57402,
+        KeyCode::Numpad4 => 57403,
+        KeyCode::Numpad5 => 57404,
+        KeyCode::Numpad6 => 57405,
+        KeyCode::Numpad7 => 57406,
+        KeyCode::Numpad8 => 57407,
+        KeyCode::Numpad9 => 57408,
+        KeyCode::NumpadDecimal => 57409,
+        KeyCode::NumpadDivide => 57410,
+        KeyCode::NumpadMultiply => 57411,
+        KeyCode::NumpadSubtract => 57412,
+        KeyCode::NumpadAdd => 57413,
+        KeyCode::NumpadEnter => 57414,
+        KeyCode::NumpadEqual => 57415,
+        KeyCode::NumpadComma => 57416,
+        _ => return None,
+    };
+
+    Some(code)
+}
+
+/// Modifiers as they are once a modifier key's own press or release is counted
+fn own_modifier(event: &KeyEvent, mut modifiers: ModifiersState) -> ModifiersState {
+    let own = match &event.logical_key {
+        Key::Named(NamedKey::Shift) => ModifiersState::SHIFT,
+        Key::Named(NamedKey::Control) => ModifiersState::CONTROL,
+        Key::Named(NamedKey::Alt) => ModifiersState::ALT,
+        Key::Named(NamedKey::Super) => ModifiersState::SUPER,
+        _ => return modifiers,
+    };
+
+    modifiers.set(own, event.state == ElementState::Pressed);
+    modifiers
+}
+
+/// The kitty modifier parameter: 1, plus 1 for Shift, 2 for Alt, 4 for Ctrl and 8 for Super
+fn kitty_modifier_param(modifiers: ModifiersState) -> u8 {
+    modifier_param(modifiers) + 8 * u8::from(modifiers.super_key())
+}
+
+/// What a key produces on a US layout, used as the kitty base layout key
+fn us_layout_char(code: KeyCode) -> Option<char> {
+    let c = match code {
+        KeyCode::KeyA => 'a',
+        KeyCode::KeyB => 'b',
+        KeyCode::KeyC => 'c',
+        KeyCode::KeyD => 'd',
+        KeyCode::KeyE => 'e',
+        KeyCode::KeyF => 'f',
+        KeyCode::KeyG => 'g',
+        KeyCode::KeyH => 'h',
+        KeyCode::KeyI => 'i',
+        KeyCode::KeyJ => 'j',
+        KeyCode::KeyK => 'k',
+        KeyCode::KeyL => 'l',
+        KeyCode::KeyM => 'm',
+        KeyCode::KeyN => 'n',
+        KeyCode::KeyO => 'o',
+        KeyCode::KeyP => 'p',
+        KeyCode::KeyQ => 'q',
+        KeyCode::KeyR => 'r',
+        KeyCode::KeyS => 's',
+        KeyCode::KeyT => 't',
+        KeyCode::KeyU => 'u',
+        KeyCode::KeyV => 'v',
+        KeyCode::KeyW => 'w',
+        KeyCode::KeyX => 'x',
+        KeyCode::KeyY => 'y',
+        KeyCode::KeyZ => 'z',
+        KeyCode::Digit0 => '0',
+        KeyCode::Digit1 => '1',
+        KeyCode::Digit2 => '2',
+        KeyCode::Digit3 => '3',
+        KeyCode::Digit4 => '4',
+        KeyCode::Digit5 => '5',
+        KeyCode::Digit6 => '6',
+        KeyCode::Digit7 => '7',
+        KeyCode::Digit8 => '8',
+        KeyCode::Digit9 => '9',
+        KeyCode::Minus => '-',
+        KeyCode::Equal => '=',
+        KeyCode::BracketLeft => '[',
+        KeyCode::BracketRight => ']',
+        KeyCode::Backslash => '\\',
+        KeyCode::Semicolon => ';',
+        KeyCode::Quote => '\'',
+        KeyCode::Backquote => '`',
+        KeyCode::Comma => ',',
+        KeyCode::Period => '.',
+        KeyCode::Slash => '/',
+        KeyCode::Space => ' ',
+        _ => return None,
+    };
+
+    Some(c)
+}
+
+fn single_char(s: &str) -> Option<char> {
+    let mut chars = s.chars();
+    chars.next().filter(|_| chars.next().is_none())
+}
+
+fn named_sequence(key: NamedKey) -> Option<Sequence> {
+    let sequence = match key {
+        NamedKey::ArrowUp => Sequence::Cursor(b'A'),
+        NamedKey::ArrowDown => Sequence::Cursor(b'B'),
+        NamedKey::ArrowRight => Sequence::Cursor(b'C'),
+        NamedKey::ArrowLeft => Sequence::Cursor(b'D'),
+        NamedKey::Home => Sequence::Cursor(b'H'),
+        NamedKey::End => Sequence::Cursor(b'F'),
+        NamedKey::F1 => Sequence::Ss3(b'P'),
+        NamedKey::F2 => Sequence::Ss3(b'Q'),
+        NamedKey::F3 => Sequence::Ss3(b'R'),
+        NamedKey::F4 => Sequence::Ss3(b'S'),
+        NamedKey::Insert => Sequence::Tilde(2),
+        NamedKey::Delete => Sequence::Tilde(3),
+        NamedKey::PageUp => Sequence::Tilde(5),
+        NamedKey::PageDown => Sequence::Tilde(6),
+        NamedKey::F5 => Sequence::Tilde(15),
+        NamedKey::F6 => Sequence::Tilde(17),
+        NamedKey::F7 => Sequence::Tilde(18),
+        NamedKey::F8 => Sequence::Tilde(19),
+        NamedKey::F9 => Sequence::Tilde(20),
+        NamedKey::F10 => Sequence::Tilde(21),
+        NamedKey::F11 => Sequence::Tilde(23),
+        NamedKey::F12 => Sequence::Tilde(24),
+        _ => return None,
+    };
+
+    Some(sequence)
+}
+
+/// The final byte a keypad key sends in application keypad mode
+fn keypad_final(code: KeyCode) -> Option<u8> {
+    let final_byte = match code {
+        KeyCode::Numpad0 => b'p',
+        KeyCode::Numpad1 => b'q',
+        KeyCode::Numpad2 => b'r',
+        KeyCode::Numpad3 => b's',
+        KeyCode::Numpad4 => b't',
+        KeyCode::Numpad5 => b'u',
+        KeyCode::Numpad6 => b'v',
+        KeyCode::Numpad7 => b'w',
+        KeyCode::Numpad8 => b'x',
+        KeyCode::Numpad9 => b'y',
+        KeyCode::NumpadMultiply => b'j',
+        KeyCode::NumpadAdd => b'k',
+        KeyCode::NumpadComma => b'l',
+        KeyCode::NumpadSubtract => b'm',
+        KeyCode::NumpadDecimal => b'n',
+        KeyCode::NumpadDivide => b'o',
+        KeyCode::NumpadEnter => b'M',
+        KeyCode::NumpadEqual => b'X',
+        _ => return None,
+    };
+
+    Some(final_byte)
+}
+
+/// xterm's modifier parameter: 1, plus 1 for Shift, 2 for Alt and 4 for Ctrl
+fn modifier_param(modifiers: ModifiersState) -> u8 {
+    1 + u8::from(modifiers.shift_key())
+        + 2 * u8::from(modifiers.alt_key())
+        + 4 * u8::from(modifiers.control_key())
+}
+
+/// The C0 control a character turns into with Ctrl held
+fn control_code(c: char) -> Option<u8> {
+    let code = match c {
+        'a'..='z' => c as u8 - b'a' + 1,
+        'A'..='Z' => c as u8 - b'A' + 1,
+        '@' | ' ' | '2' => 0x00,
+        '[' | '3' => 0x1b,
+        '\\' | '4' => 0x1c,
+        ']' | '5' => 0x1d,
+        '^' | '~' | '6' => 0x1e,
+        '_' | '/' | '7' => 0x1f,
+        '?' | '8' => 0x7f,
+        _ => return None,
+    };
+
+    Some(code)
+}
+
+/// Prefix `bytes` with ESC when Alt is held
+fn with_alt(bytes: &[u8], alt: bool) -> Vec<u8> {
+    let mut out = Vec::with_capacity(bytes.len() + 1);
+    if alt {
+        out.push(0x1b);
+    }
+    out.extend_from_slice(bytes);
+    out
+}
@@ -1,13 +1,22 @@
 mod app;
+mod clipboard;
+mod commands;
 mod config;
+mod cwd;
+mod keyboard;
+mod mouse;
+mod notify;
+mod palette;
 mod pty;
 mod renderer;
+mod search;
 mod shaper;
 mod terminal;
 
 use crate::{
     app::{App, CustomEvent},
     config::Config,
+    notify::default_notifier,
 };
 use std::{error::Error, sync::Arc};
 use winit::event_loop::EventLoop;
@@ -22,7 +31,7 @@ fn main() -> Result<(), Box<dyn Error>> {
     let event_loop = EventLoop::<CustomEvent>::with_user_event().build()?;
     let proxy = event_loop.create_proxy();
 
-    let mut app = App::new(proxy, config);
+    let mut app = App::new(proxy, config, default_notifier());
 
     event_loop.run_app(&mut app)?;
 
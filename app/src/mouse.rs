@@ -0,0 +1,165 @@
+use winit::keyboard::ModifiersState;
+
+/// Which mouse events the program asked for (DEC private modes 9, 1000, 1002, 1003)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MouseMode {
+    #[default]
+    Off,
+    /// Presses only, without modifiers
+    X10,
+    /// Presses and releases
+    Click,
+    /// Click, plus motion while a button is held
+    Drag,
+    /// Click, plus all motion
+    Motion,
+}
+
+/// How reports are encoded (DEC private modes 1005, 1006, 1016)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MouseEncoding {
+    /// `CSI M` followed by three bytes, coordinates up to 223
+    #[default]
+    Default,
+    /// `CSI M` with coordinates as UTF-8 characters, up to 2015
+    Utf8,
+    /// `CSI < b ; x ; y M/m`
+    Sgr,
+    /// SGR with pixel coordinates instead of cells
+    SgrPixels,
+}
+
+/// Mouse reporting state requested by the program
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MouseReporting {
+    pub mode: MouseMode,
+    pub encoding: MouseEncoding,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseButton {
+    Left,
+    Middle,
+    Right,
+    WheelUp,
+    WheelDown,
+    WheelLeft,
+    WheelRight,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseAction {
+    Press,
+    Release,
+    /// Motion with the given button held, if any
+    Motion,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MouseEvent {
+    pub action: MouseAction,
+    pub button: Option<MouseButton>,
+    /// 0-based cell
+    pub col: usize,
+    pub row: usize,
+    /// Position in pixels, for SGR-pixels
+    pub pixels: (usize, usize),
+    pub modifiers: ModifiersState,
+}
+
+impl MouseReporting {
+    /// Whether mouse events go to the program rather than to local selection
+    pub fn is_active(&self) -> bool {
+        self.mode != MouseMode::Off
+    }
+
+    /// Encode `event` for the PTY, or `None` if the current mode doesn't report it
+    pub fn encode(&self, event: &MouseEvent) -> Option<Vec<u8>> {
+        let wanted = match (self.mode, event.action) {
+            (MouseMode::Off, _) => false,
+            (_, MouseAction::Press) => true,
+            (MouseMode::X10, _) => false,
+            (_, MouseAction::Release) => !is_wheel(event.button),
+            (MouseMode::Motion, MouseAction::Motion) => true,
+            (MouseMode::Drag, MouseAction::Motion) => event.button.is_some(),
+            (MouseMode::Click, MouseAction::Motion) => false,
+        };
+        if !wanted {
+            return None;
+        }
+
+        let mut code = match event.button {
+            Some(MouseButton::Left) => 0,
+            Some(MouseButton::Middle) => 1,
+            Some(MouseButton::Right) => 2,
+            Some(MouseButton::WheelUp) => 64,
+            Some(MouseButton::WheelDown) => 65,
+            Some(MouseButton::WheelLeft) => 66,
+            Some(MouseButton::WheelRight) => 67,
+            None => 3,
+        };
+
+        if event.action == MouseAction::Motion {
+            code += 32;
+        }
+
+        if self.mode != MouseMode::X10 {
+            if event.modifiers.shift_key() {
+                code += 4;
+            }
+            if event.modifiers.alt_key() {
+                code += 8;
+            }
+            if event.modifiers.control_key() {
+                code += 16;
+            }
+        }
+
+        let release = event.action == MouseAction::Release;
+
+        match self.encoding {
+            MouseEncoding::Sgr | MouseEncoding::SgrPixels => {
+                let (x, y) = if self.encoding == MouseEncoding::SgrPixels {
+                    event.pixels
+                } else {
+                    (event.col, event.row)
+                };
+                let suffix = if release { 'm' } else { 'M' };
+                Some(format!("\x1b[<{code};{};{}{suffix}", x + 1, y + 1).into_bytes())
+            }
+            MouseEncoding::Default | MouseEncoding::Utf8 => {
+                // These can't tell which button was released
+                if release {
+                    code = (code & !0b11) | 3;
+                }
+
+                let mut out = b"\x1b[M".to_vec();
+                out.push(32 + code);
+
+                for value in [event.col, event.row] {
+                    let value = 32 + 1 + value;
+                    if self.encoding == MouseEncoding::Default {
+                        out.push(u8::try_from(value).ok()?);
+                    } else {
+                        let c = char::from_u32(value as u32).filter(|_| value < 2048)?;
+                        out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                    }
+                }
+
+                Some(out)
+            }
+        }
+    }
+}
+
+fn is_wheel(button: Option<MouseButton>) -> bool {
+    matches!(
+        button,
+        Some(
+            MouseButton::WheelUp
+                | MouseButton::WheelDown
+                | MouseButton::WheelLeft
+                | MouseButton::WheelRight
+        )
+    )
+}
@@ -0,0 +1,244 @@
+use std::collections::VecDeque;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{Sender, bounded};
+
+/// A desktop notification asked for by the program (OSC 9 or OSC 777),
+/// or sent when a long command finishes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    pub title: String,
+    pub body: String,
+}
+
+/// Shows notifications on the desktop. `App` takes any implementation,
+/// so a mock can stand in for the real one
+pub trait Notifier {
+    /// Show `notification`. Failures are logged rather than returned,
+    /// as there's nothing the terminal can do about them
+    fn notify(&mut self, notification: &Notification);
+}
+
+/// The notifier for this platform
+pub fn default_notifier() -> Box<dyn Notifier> {
+    #[cfg(target_os = "linux")]
+    return Box::new(BackgroundNotifier::spawn(DbusNotifier::default()));
+
+    #[cfg(not(target_os = "linux"))]
+    return Box::new(BackgroundNotifier::spawn(LogNotifier));
+}
+
+/// Hands notifications to another notifier running on its own thread, so a
+/// slow notification service can't hold up the caller. Notifications past
+/// `MAX_PER_SECOND`, or past what the thread has room to queue, are dropped
+pub struct BackgroundNotifier {
+    sender: Sender<Notification>,
+    /// When the notifications of the last second were sent, oldest first
+    recent: VecDeque<Instant>,
+}
+
+impl BackgroundNotifier {
+    const MAX_PER_SECOND: usize = 3;
+    const QUEUE_LEN: usize = 16;
+
+    pub fn spawn(mut notifier: impl Notifier + Send + 'static) -> Self {
+        let (sender, receiver) = bounded::<Notification>(Self::QUEUE_LEN);
+
+        thread::spawn(move || {
+            for notification in receiver {
+                notifier.notify(&notification);
+            }
+        });
+
+        Self {
+            sender,
+            recent: VecDeque::new(),
+        }
+    }
+}
+
+impl Notifier for BackgroundNotifier {
+    fn notify(&mut self, notification: &Notification) {
+        let now = Instant::now();
+        while self
+            .recent
+            .front()
+            .is_some_and(|sent| now.duration_since(*sent) >= Duration::from_secs(1))
+        {
+            self.recent.pop_front();
+        }
+
+        if self.recent.len() >= Self::MAX_PER_SECOND {
+            log::debug!(
+                "Dropping notification, too many sent: {}",
+                notification.title
+            );
+            return;
+        }
+
+        match self.sender.try_send(notification.clone()) {
+            Ok(()) => self.recent.push_back(now),
+            Err(e) => log::warn!("Dropping notification: {e}"),
+        }
+    }
+}
+
+/// Sends notifications to `org.freedesktop.Notifications` on the session bus.
+/// Connects on first use, so a missing bus only matters once something is sent
+#[cfg(target_os = "linux")]
+#[derive(Default)]
+pub struct DbusNotifier {
+    connection: Option<zbus::blocking::Connection>,
+}
+
+#[cfg(target_os = "linux")]
+impl DbusNotifier {
+    fn send(&mut self, notification: &Notification) -> zbus::Result<()> {
+        use std::collections::HashMap;
+        use zbus::zvariant::Value;
+
+        let connection = match &mut self.connection {
+            Some(connection) => connection,
+            connection => connection.insert(zbus::blocking::Connection::session()?),
+        };
+
+        let actions: &[&str] = &[];
+        let hints: HashMap<&str, Value> = HashMap::new();
+        // -1 leaves the timeout to the notification server
+        let expire_timeout = -1i32;
+
+        connection.call_method(
+            Some("org.freedesktop.Notifications"),
+            "/org/freedesktop/Notifications",
+            Some("org.freedesktop.Notifications"),
+            "Notify",
+            &(
+                "bnuuy",
+                0u32,
+                "utilities-terminal",
+                notification.title.as_str(),
+                notification.body.as_str(),
+                actions,
+                hints,
+                expire_timeout,
+            ),
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Notifier for DbusNotifier {
+    fn notify(&mut self, notification: &Notification) {
+        if let Err(e) = self.send(notification) {
+            log::warn!("Failed to send notification: {e}");
+            // Reconnect next time, in case the bus went away
+            self.connection = None;
+        }
+    }
+}
+
+/// Logs notifications, where there's no notification service to send them to
+#[cfg(not(target_os = "linux"))]
+pub struct LogNotifier;
+
+#[cfg(not(target_os = "linux"))]
+impl Notifier for LogNotifier {
+    fn notify(&mut self, notification: &Notification) {
+        log::info!("Notification: {} {}", notification.title, notification.body);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::config::Config;
+    use crate::terminal::TerminalState;
+
+    /// Keeps every notification it's given, for tests to look at
+    #[derive(Clone, Default)]
+    struct RecordingNotifier {
+        sent: Arc<Mutex<Vec<Notification>>>,
+    }
+
+    impl Notifier for RecordingNotifier {
+        fn notify(&mut self, notification: &Notification) {
+            self.sent.lock().unwrap().push(notification.clone());
+        }
+    }
+
+    impl RecordingNotifier {
+        /// Wait for the worker thread to deliver `count` notifications
+        fn wait_for(&self, count: usize) -> Vec<Notification> {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while self.sent.lock().unwrap().len() < count && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(5));
+            }
+            self.sent.lock().unwrap().clone()
+        }
+    }
+
+    fn notification(title: &str) -> Notification {
+        Notification {
+            title: title.to_string(),
+            body: String::new(),
+        }
+    }
+
+    #[test]
+    fn background_notifier_forwards_notifications() {
+        let recorder = RecordingNotifier::default();
+        let mut notifier = BackgroundNotifier::spawn(recorder.clone());
+
+        notifier.notify(&notification("one"));
+        notifier.notify(&notification("two"));
+
+        assert_eq!(
+            recorder.wait_for(2),
+            [notification("one"), notification("two")]
+        );
+    }
+
+    #[test]
+    fn background_notifier_rate_limits() {
+        let recorder = RecordingNotifier::default();
+        let mut notifier = BackgroundNotifier::spawn(recorder.clone());
+
+        for i in 0..BackgroundNotifier::MAX_PER_SECOND + 2 {
+            notifier.notify(&notification(&i.to_string()));
+        }
+
+        recorder.wait_for(BackgroundNotifier::MAX_PER_SECOND);
+        // Give anything sent past the limit time to arrive
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(
+            recorder.sent.lock().unwrap().len(),
+            BackgroundNotifier::MAX_PER_SECOND
+        );
+    }
+
+    #[test]
+    fn osc_notifications() {
+        let mut term = TerminalState::new(80, 24, Arc::new(Config::default()));
+
+        term.feed(b"\x1b]9;Build done\x07");
+        term.feed(b"\x1b]9;4;1;50\x07");
+        term.feed(b"\x1b]777;notify;Title;Body; with semicolon\x1b\\");
+
+        assert_eq!(
+            term.take_notifications(),
+            [
+                notification("Build done"),
+                Notification {
+                    title: "Title".to_string(),
+                    body: "Body; with semicolon".to_string(),
+                },
+            ]
+        );
+        assert!(term.take_notifications().is_empty());
+    }
+}
@@ -0,0 +1,181 @@
+use std::hash::{Hash, Hasher};
+
+use screen_grid::{Color, Rgb};
+
+use crate::config::Config;
+
+/// Slots past the 256 indexed colors
+pub const FOREGROUND: usize = 256;
+pub const BACKGROUND: usize = 257;
+pub const CURSOR: usize = 258;
+const LEN: usize = 259;
+
+/// Colors the program can redefine at runtime (OSC 4, 10, 11 and 12):
+/// the 256 indexed colors, then the default foreground, background and cursor
+#[derive(Clone)]
+pub struct Palette {
+    colors: [Rgb; LEN],
+    /// What `reset` goes back to
+    defaults: [Rgb; LEN],
+}
+
+impl Palette {
+    pub fn new(config: &Config) -> Self {
+        let colors = &config.colors;
+
+        let mut defaults = std::array::from_fn(|idx| match idx {
+            0..16 => colors.palette[idx].into(),
+            FOREGROUND => colors.foreground.into(),
+            BACKGROUND => colors.background.into(),
+            CURSOR => colors.cursor.into(),
+            _ => xterm_256(idx as u8),
+        });
+        for indexed in &colors.indexed {
+            defaults[usize::from(indexed.index)] = indexed.color.into();
+        }
+
+        Self {
+            colors: defaults,
+            defaults,
+        }
+    }
+
+    /// Color in slot `idx`, either an indexed color or one of the special slots
+    pub fn get(&self, idx: usize) -> Rgb {
+        self.colors[idx]
+    }
+
+    pub fn background(&self) -> Rgb {
+        self.colors[BACKGROUND]
+    }
+
+    pub fn cursor(&self) -> Rgb {
+        self.colors[CURSOR]
+    }
+
+    /// What a cell's foreground color looks like with this palette
+    pub fn fg(&self, color: Color) -> Rgb {
+        self.resolve(color, FOREGROUND)
+    }
+
+    /// What a cell's background color looks like with this palette
+    pub fn bg(&self, color: Color) -> Rgb {
+        self.resolve(color, BACKGROUND)
+    }
+
+    fn resolve(&self, color: Color, default: usize) -> Rgb {
+        match color {
+            Color::Default => self.colors[default],
+            Color::Indexed(idx) => self.colors[usize::from(idx)],
+            Color::Rgb(rgb) => rgb,
+        }
+    }
+
+    pub fn set(&mut self, idx: usize, color: Rgb) {
+        if let Some(slot) = self.colors.get_mut(idx) {
+            *slot = color;
+        }
+    }
+
+    /// Put slot `idx` back to its configured color
+    pub fn reset(&mut self, idx: usize) {
+        if idx < LEN {
+            self.colors[idx] = self.defaults[idx];
+        }
+    }
+
+    /// Put all 256 indexed colors back, leaving the special slots alone
+    pub fn reset_indexed(&mut self) {
+        self.colors[..FOREGROUND].copy_from_slice(&self.defaults[..FOREGROUND]);
+    }
+}
+
+/// Only the current colors count: two palettes that draw the same hash the same
+impl Hash for Palette {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.colors.hash(state);
+    }
+}
+
+/// Parse an X11 color spec, as used by OSC 4 and 10-12: `rgb:r/g/b` with 1-4 hex
+/// digits per channel, or `#rgb`, `#rrggbb`, `#rrrgggbbb` and `#rrrrggggbbbb`
+pub fn parse_color(spec: &str) -> Option<Rgb> {
+    if let Some(channels) = spec.strip_prefix("rgb:") {
+        let mut parts = channels.split('/');
+        let mut channel = || {
+            let hex = parts.next()?;
+            if hex.is_empty() || hex.len() > 4 {
+                return None;
+            }
+
+            // Scale so that all ones is full intensity, whatever the number of digits
+            let value = u32::from_str_radix(hex, 16).ok()?;
+            let max = (1u32 << (4 * hex.len())) - 1;
+            Some(((value * 255 + max / 2) / max) as u8)
+        };
+
+        let color = Rgb(channel()?, channel()?, channel()?);
+        return parts.next().is_none().then_some(color);
+    }
+
+    let hex = spec.strip_prefix('#')?;
+    if hex.is_empty() || hex.len() % 3 != 0 || hex.len() > 12 {
+        return None;
+    }
+
+    // Digits past the first two are dropped, and a single digit is the high nibble
+    let digits = hex.len() / 3;
+    let channel = |i: usize| {
+        let value = u32::from_str_radix(&hex[i * digits..(i + 1) * digits], 16).ok()?;
+        Some(match digits {
+            1 => (value << 4) as u8,
+            _ => (value >> (4 * (digits - 2))) as u8,
+        })
+    };
+
+    Some(Rgb(channel(0)?, channel(1)?, channel(2)?))
+}
+
+/// Format a color the way xterm answers color queries
+pub fn format_color(color: Rgb) -> String {
+    let Rgb(r, g, b) = color;
+    format!("rgb:{r:02x}{r:02x}/{g:02x}{g:02x}/{b:02x}{b:02x}")
+}
+
+/// xterm's 16 ANSI colors, the default for `colors.palette`
+pub const XTERM_16: [Rgb; 16] = [
+    Rgb(0x00, 0x00, 0x00),
+    Rgb(0xCD, 0x00, 0x00),
+    Rgb(0x00, 0xCD, 0x00),
+    Rgb(0xCD, 0xCD, 0x00),
+    Rgb(0x00, 0x00, 0xEE),
+    Rgb(0xCD, 0x00, 0xCD),
+    Rgb(0x00, 0xCD, 0xCD),
+    Rgb(0xE5, 0xE5, 0xE5),
+    Rgb(0x7F, 0x7F, 0x7F),
+    Rgb(0xFF, 0x00, 0x00),
+    Rgb(0x00, 0xFF, 0x00),
+    Rgb(0xFF, 0xFF, 0x00),
+    Rgb(0x5C, 0x5C, 0xFF),
+    Rgb(0xFF, 0x00, 0xFF),
+    Rgb(0x00, 0xFF, 0xFF),
+    Rgb(0xFF, 0xFF, 0xFF),
+];
+
+/// xterm's default for indexed color `idx`
+fn xterm_256(idx: u8) -> Rgb {
+    match idx {
+        0..=15 => XTERM_16[usize::from(idx)],
+        // 6x6x6 color cube
+        16..=231 => {
+            const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+            let code = usize::from(idx - 16);
+            Rgb(LEVELS[code / 36], LEVELS[(code / 6) % 6], LEVELS[code % 6])
+        }
+        // Grayscale ramp
+        232..=255 => {
+            let gray = (idx - 232) * 10 + 8;
+            Rgb(gray, gray, gray)
+        }
+    }
+}
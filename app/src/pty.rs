@@ -1,5 +1,5 @@
 use portable_pty::{Child, CommandBuilder, MasterPty, PtySize, native_pty_system};
-use std::{io::Write, sync::Arc};
+use std::{io::Write, path::Path, sync::Arc};
 
 use crate::config::Config;
 
@@ -9,7 +9,9 @@ pub struct PtyHandles {
     pub child: Box<dyn Child + Send>,
 }
 
-pub fn spawn_shell(cols: u16, rows: u16, config: Arc<Config>) -> PtyHandles {
+/// Start the configured shell on a new PTY, in `cwd` if given,
+/// such as the directory the focused shell last reported
+pub fn spawn_shell(cols: u16, rows: u16, config: Arc<Config>, cwd: Option<&Path>) -> PtyHandles {
     let pty_system = native_pty_system();
     let pair = pty_system
         .openpty(PtySize {
@@ -22,6 +24,9 @@ pub fn spawn_shell(cols: u16, rows: u16, config: Arc<Config>) -> PtyHandles {
 
     let mut cmd = CommandBuilder::new(&config.shell[0]);
     cmd.args(&config.shell[1..]);
+    if let Some(cwd) = cwd {
+        cmd.cwd(cwd);
+    }
 
     cmd.env("TERM", "xterm-256color");
 
@@ -1,10 +1,14 @@
-use crate::{config::Config, terminal::TerminalState};
+use crate::{
+    config::{Config, ConfigColor},
+    search::SearchBar,
+    terminal::TerminalState,
+};
 use glyphon::{
     Attrs, Buffer, Cache, Family, FontSystem, Metrics, Resolution, Shaping, SwashCache, TextArea,
     TextAtlas, TextBounds, TextRenderer, Viewport, fontdb,
 };
 use lru::LruCache;
-use screen_grid::{CellFlags, Rgb};
+use screen_grid::{CellFlags, Point, Rgb};
 use std::{
     hash::{DefaultHasher, Hash, Hasher},
     num::NonZeroUsize,
@@ -28,21 +32,22 @@ pub struct Renderer {
     globals_bind_group: wgpu::BindGroup,
 
     bg: BgRenderer,
-    underline: UnderlineRenderer,
+    lines: [LineRenderer; LineKind::ALL.len()],
     undercurl: UndercurlRenderer,
 
     bg_clear_color: wgpu::Color,
 
     bg_cache: LruCache<u64, Vec<BgInstance>>,
-    underline_cache: LruCache<u64, Vec<UnderlineInstance>>,
+    line_cache: LruCache<u64, Vec<(LineKind, LineInstance)>>,
     undercurl_cache: LruCache<u64, Vec<UndercurlInstance>>,
     cache: Cache,
 
     atlas: TextAtlas,
     text_renderer: glyphon::TextRenderer,
+    search_bar_buffer: Option<Buffer>,
 
     last_scroll_offset: usize,
-    last_selection: Option<((usize, usize), (usize, usize))>,
+    last_selection: Option<(Point, Point)>,
     last_hovered_link: Option<u32>,
 
     config: Arc<Config>,
@@ -114,12 +119,12 @@ impl UndercurlInstance {
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
-struct UnderlineInstance {
+struct LineInstance {
     position: [f32; 2], // top-left corner of the cell, in px
-    color: [u8; 4],     // color of the underline
+    color: [u8; 4],     // color of the line
 }
 
-impl UnderlineInstance {
+impl LineInstance {
     const ATTRIBS: [wgpu::VertexAttribute; 2] =
         wgpu::vertex_attr_array![5 => Float32x2, 6 => Unorm8x4];
 
@@ -178,10 +183,72 @@ struct UndercurlRenderer {
     instance_capacity: u64,
 }
 
+/// Decorations drawn as straight lines across a cell, each with its own shader
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LineKind {
+    Underline,
+    DoubleUnderline,
+    DottedUnderline,
+    DashedUnderline,
+    Strikethrough,
+    Overline,
+}
+
+impl LineKind {
+    const ALL: [LineKind; 6] = [
+        LineKind::Underline,
+        LineKind::DoubleUnderline,
+        LineKind::DottedUnderline,
+        LineKind::DashedUnderline,
+        LineKind::Strikethrough,
+        LineKind::Overline,
+    ];
+
+    /// The underline style set in `flags`, if any. Undercurl has its own renderer
+    fn underline(flags: CellFlags) -> Option<Self> {
+        if flags.contains(CellFlags::UNDERLINE) {
+            Some(LineKind::Underline)
+        } else if flags.contains(CellFlags::DOUBLE_UNDERLINE) {
+            Some(LineKind::DoubleUnderline)
+        } else if flags.contains(CellFlags::DOTTED_UNDERLINE) {
+            Some(LineKind::DottedUnderline)
+        } else if flags.contains(CellFlags::DASHED_UNDERLINE) {
+            Some(LineKind::DashedUnderline)
+        } else {
+            None
+        }
+    }
+
+    /// Shader label and source
+    fn shader(self) -> (&'static str, &'static str) {
+        match self {
+            LineKind::Underline => ("underline.wgsl", include_str!("shaders/underline.wgsl")),
+            LineKind::DoubleUnderline => (
+                "double_underline.wgsl",
+                include_str!("shaders/double_underline.wgsl"),
+            ),
+            LineKind::DottedUnderline => (
+                "dotted_underline.wgsl",
+                include_str!("shaders/dotted_underline.wgsl"),
+            ),
+            LineKind::DashedUnderline => (
+                "dashed_underline.wgsl",
+                include_str!("shaders/dashed_underline.wgsl"),
+            ),
+            LineKind::Strikethrough => (
+                "strikethrough.wgsl",
+                include_str!("shaders/strikethrough.wgsl"),
+            ),
+            LineKind::Overline => ("overline.wgsl", include_str!("shaders/overline.wgsl")),
+        }
+    }
+}
+
 #[derive(Debug)]
-struct UnderlineRenderer {
+struct LineRenderer {
+    kind: LineKind,
     pipeline: RenderPipeline,
-    instances: Vec<UnderlineInstance>,
+    instances: Vec<LineInstance>,
     instance_buffer: wgpu::Buffer,
     instance_capacity: u64,
 }
@@ -262,24 +329,21 @@ impl Renderer {
         let bg = BgRenderer::new(&gpu.device, gpu.config.format, &globals_bind_group_layout);
         let undercurl =
             UndercurlRenderer::new(&gpu.device, gpu.config.format, &globals_bind_group_layout);
-        let underline =
-            UnderlineRenderer::new(&gpu.device, gpu.config.format, &globals_bind_group_layout);
+        let lines = LineKind::ALL.map(|kind| {
+            LineRenderer::new(
+                &gpu.device,
+                gpu.config.format,
+                &globals_bind_group_layout,
+                kind,
+            )
+        });
 
         let bg_cache = LruCache::new(NonZeroUsize::new(15000).unwrap());
-        let underline_cache = LruCache::new(NonZeroUsize::new(12000).unwrap());
+        let line_cache = LruCache::new(NonZeroUsize::new(12000).unwrap());
         let undercurl_cache = LruCache::new(NonZeroUsize::new(12000).unwrap());
 
-        let bg_clear_color = {
-            let (r, g, b) = config.colors.background;
-            let a = config.background_opacity;
-            let srgb_to_linear_f64 = |c: u8| (c as f64 / 255.0).powf(2.2);
-            wgpu::Color {
-                r: srgb_to_linear_f64(r),
-                g: srgb_to_linear_f64(g),
-                b: srgb_to_linear_f64(b),
-                a: a as f64,
-            }
-        };
+        let bg_clear_color =
+            clear_color(config.colors.background.into(), config.background_opacity);
 
         Self {
             window,
@@ -289,14 +353,15 @@ impl Renderer {
             globals_bind_group,
             bg_clear_color,
             bg,
-            underline,
+            lines,
             undercurl,
             bg_cache,
-            underline_cache,
+            line_cache,
             undercurl_cache,
             cache,
             atlas,
             text_renderer,
+            search_bar_buffer: None,
             last_scroll_offset: 0,
             last_selection: None,
             last_hovered_link: None,
@@ -307,6 +372,13 @@ impl Renderer {
         }
     }
 
+    /// Switch to a reloaded config. Font size changes only apply to new windows,
+    /// since the cell size is fixed once the grid is laid out
+    pub fn set_config(&mut self, config: Arc<Config>) {
+        self.config = config;
+        self.decorations_dirty = true;
+    }
+
     pub fn resize(&mut self, w: u32, h: u32) {
         if w == 0 || h == 0 || (w, h) == (self.gpu.config.width, self.gpu.config.height) {
             return;
@@ -332,13 +404,15 @@ impl Renderer {
         self.window.id()
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn render(
         &mut self,
         font_system: &mut FontSystem,
         swash_cache: &mut SwashCache,
         term: &mut TerminalState,
-        selection: Option<((usize, usize), (usize, usize))>,
+        selection: Option<(Point, Point)>,
         hovered_link_id: Option<u32>,
+        search: Option<&SearchBar>,
         top_padding: f32,
     ) {
         let frame = match self.gpu.surface.get_current_texture() {
@@ -371,21 +445,43 @@ impl Renderer {
             .queue
             .write_buffer(&self.globals_buffer, 0, bytemuck::cast_slice(&[globals]));
 
+        // The program may have changed the background color
+        self.bg_clear_color =
+            clear_color(term.palette.background(), self.config.background_opacity);
+
         let needs_decoration_update = term.is_dirty
             || self.last_scroll_offset != term.scroll_offset
             || self.last_selection != selection
             || self.last_hovered_link != hovered_link_id
             || self.decorations_dirty;
 
+        let display_lines = term.display_lines();
+
         if needs_decoration_update {
-            self.prepare_decorations(term, selection, hovered_link_id, top_padding);
+            self.prepare_decorations(
+                term,
+                &display_lines,
+                selection,
+                hovered_link_id,
+                search,
+                top_padding,
+            );
             self.decorations_dirty = false;
         }
 
-        let text_areas: Vec<TextArea> = (0..self.grid_size(top_padding).1)
+        let grid_rows = self.grid_size(top_padding).1;
+        let search_bar_row = search.map(|_| grid_rows.saturating_sub(1));
+
+        if let Some(search) = search {
+            self.prepare_search_bar_text(font_system, search);
+        }
+
+        let mut text_areas: Vec<TextArea> = (0..grid_rows)
+            .filter(|&y| Some(y) != search_bar_row)
             .filter_map(|y| {
-                term.grid()
-                    .get_display_row(y, term.scroll_offset)
+                display_lines
+                    .get(y)
+                    .and_then(|&line| term.grid().loaded_row(line))
                     .and_then(|row| {
                         if row.is_dirty {
                             None
@@ -410,6 +506,23 @@ impl Renderer {
             })
             .collect();
 
+        if let (Some(y), Some(buffer)) = (search_bar_row, &self.search_bar_buffer) {
+            text_areas.push(TextArea {
+                buffer,
+                left: 0.0,
+                top: (y as f32 * self.cell_size.1) + top_padding,
+                scale: 1.0,
+                bounds: TextBounds {
+                    left: 0,
+                    top: 0,
+                    right: self.surface_size().0 as i32,
+                    bottom: self.surface_size().1 as i32,
+                },
+                custom_glyphs: &[],
+                default_color: glyphon::Color::rgb(0xFF, 0xFF, 0xFF),
+            });
+        }
+
         {
             let Self {
                 gpu,
@@ -417,7 +530,7 @@ impl Renderer {
                 cache,
                 text_renderer,
                 bg,
-                underline,
+                lines,
                 undercurl,
                 vertex_buffer,
                 globals_bind_group,
@@ -470,13 +583,13 @@ impl Renderer {
                 rpass.draw(0..BG_VERTICES.len() as u32, 0..bg.instances.len() as u32);
             }
 
-            if !underline.instances.is_empty() {
-                rpass.set_pipeline(&underline.pipeline);
-                rpass.set_vertex_buffer(1, underline.instance_buffer.slice(..));
-                rpass.draw(
-                    0..BG_VERTICES.len() as u32,
-                    0..underline.instances.len() as u32,
-                );
+            // Strikethrough goes over the text rather than under it
+            let (over_text, under_text): (Vec<_>, Vec<_>) = lines
+                .iter()
+                .partition(|line| line.kind == LineKind::Strikethrough);
+
+            for line in under_text {
+                line.draw(&mut rpass);
             }
 
             if !undercurl.instances.is_empty() {
@@ -489,6 +602,10 @@ impl Renderer {
             }
 
             text_renderer.render(atlas, &viewport, &mut rpass).unwrap();
+
+            for line in over_text {
+                line.draw(&mut rpass);
+            }
         }
 
         self.gpu.queue.submit(Some(encoder.finish()));
@@ -503,22 +620,33 @@ impl Renderer {
     fn prepare_decorations(
         &mut self,
         term: &mut TerminalState,
-        selection: Option<((usize, usize), (usize, usize))>,
+        display_lines: &[usize],
+        selection: Option<(Point, Point)>,
         hovered_link_id: Option<u32>,
+        search: Option<&SearchBar>,
         top_padding: f32,
     ) {
-        let (_grid_cols, grid_rows) = self.grid_size(top_padding);
-        let cursor_visible = term.cursor_visible && term.scroll_offset == 0;
-
-        let default_bg_rgb = screen_grid::Rgb(
-            self.config.colors.background.0,
-            self.config.colors.background.1,
-            self.config.colors.background.2,
-        );
+        let (grid_cols, grid_rows) = self.grid_size(top_padding);
+
+        // Folds can move the cursor's line to another row of the view
+        let cursor_line = term.grid().scrollback_len() + term.grid().cur_y;
+        let cursor_row = (term.cursor_visible && term.scroll_offset == 0)
+            .then(|| display_lines.iter().position(|&line| line == cursor_line))
+            .flatten();
+
+        let default_bg_rgb = term.palette.background();
+        // Cells hold palette references, so the palette is part of every row's look
+        let palette_hash = {
+            let mut hasher = DefaultHasher::new();
+            term.palette.hash(&mut hasher);
+            hasher.finish()
+        };
 
         // Clear old instance data
         self.bg.instances.clear();
-        self.underline.instances.clear();
+        for line in &mut self.lines {
+            line.instances.clear();
+        }
         self.undercurl.instances.clear();
 
         // Draw fake titlebar if needed
@@ -531,24 +659,18 @@ impl Renderer {
         }
 
         // Loop over every visible row
-        for y in 0..grid_rows {
-            if let Some(grid_row) = term.grid().get_display_row(y, term.scroll_offset) {
+        for (y, &line) in display_lines.iter().enumerate().take(grid_rows) {
+            if let Some(grid_row) = term.grid().loaded_row(line) {
                 let mut hasher = DefaultHasher::new();
                 grid_row.hash(&mut hasher);
+                palette_hash.hash(&mut hasher);
 
-                if cursor_visible && y == term.grid().cur_y {
+                if cursor_row == Some(y) {
                     term.grid().cur_x.hash(&mut hasher);
                 }
 
-                let row_hovered_link_id = if let Some(id) = hovered_link_id {
-                    if grid_row.cells.iter().any(|c| c.link_id == Some(id)) {
-                        Some(id)
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                };
+                let row_hovered_link_id = hovered_link_id
+                    .filter(|&id| grid_row.cells.iter().any(|c| c.link_id == Some(id)));
                 row_hovered_link_id.hash(&mut hasher);
                 let row_hash = hasher.finish();
 
@@ -563,13 +685,13 @@ impl Renderer {
                             color: inst.color,
                         }));
 
-                    if let Some(cached_underlines) = self.underline_cache.get(&row_hash) {
-                        self.underline
-                            .instances
-                            .extend(cached_underlines.iter().map(|inst| UnderlineInstance {
+                    if let Some(cached_lines) = self.line_cache.get(&row_hash) {
+                        for (kind, inst) in cached_lines {
+                            self.lines[*kind as usize].instances.push(LineInstance {
                                 position: [inst.position[0], y_pos],
                                 color: inst.color,
-                            }));
+                            });
+                        }
                     }
 
                     if let Some(cached_undercurls) = self.undercurl_cache.get(&row_hash) {
@@ -583,15 +705,19 @@ impl Renderer {
                 } else {
                     // Slow path
                     let mut row_bgs = Vec::new();
-                    let mut row_underlines = Vec::new();
+                    let mut row_lines = Vec::new();
                     let mut row_undercurls = Vec::new();
 
                     for (x, cell) in grid_row.cells.iter().enumerate() {
-                        let is_cursor =
-                            cursor_visible && y == term.grid().cur_y && x == term.grid().cur_x;
+                        // A cursor on a wide glyph covers its spacer as well
+                        let cur_x = term.grid().cur_x;
+                        let is_cursor = cursor_row == Some(y)
+                            && (x == cur_x
+                                || (x == cur_x + 1
+                                    && cell.flags.contains(CellFlags::WIDE_CHAR_SPACER)));
 
-                        let mut fg = cell.fg;
-                        let mut bg = cell.bg;
+                        let mut fg = term.palette.fg(cell.fg);
+                        let mut bg = term.palette.bg(cell.bg);
 
                         if cell.flags.contains(CellFlags::INVERSE) {
                             std::mem::swap(&mut fg, &mut bg);
@@ -609,29 +735,53 @@ impl Renderer {
                         // If it's the cursor, draw another block
                         // on top, using the cursor color
                         if is_cursor {
-                            let (r, g, b) = self.config.colors.cursor;
+                            let Rgb(r, g, b) = term.palette.cursor();
                             row_bgs.push(BgInstance {
                                 position: [x as f32 * self.cell_size.0, 0.0],
                                 color: [r, g, b, 255],
                             });
                         }
 
+                        // Concealed text hides its decorations along with the glyph
+                        if cell.flags.contains(CellFlags::HIDDEN) {
+                            continue;
+                        }
+
                         // Decorations
                         let decoration_fg = if is_cursor {
-                            let (r, g, b) = self.config.colors.cursor_text;
-                            Rgb(r, g, b)
+                            self.config.colors.cursor_text.into()
                         } else {
                             fg
                         };
                         let final_fg_color =
                             [decoration_fg.0, decoration_fg.1, decoration_fg.2, 255];
+                        // Underlines take the SGR 58 color when there is one
+                        let underline_rgb = match cell.underline_color {
+                            Some(color) if !is_cursor => term.palette.fg(color),
+                            _ => decoration_fg,
+                        };
+                        let underline_color =
+                            [underline_rgb.0, underline_rgb.1, underline_rgb.2, 255];
                         let cell_x_pos = x as f32 * self.cell_size.0;
 
-                        if cell.flags.contains(CellFlags::UNDERLINE) {
-                            row_underlines.push(UnderlineInstance {
-                                position: [cell_x_pos, 0.0],
-                                color: final_fg_color,
-                            });
+                        let mut push_line = |kind, color| {
+                            row_lines.push((
+                                kind,
+                                LineInstance {
+                                    position: [cell_x_pos, 0.0],
+                                    color,
+                                },
+                            ));
+                        };
+
+                        if let Some(kind) = LineKind::underline(cell.flags) {
+                            push_line(kind, underline_color);
+                        }
+                        if cell.flags.contains(CellFlags::STRIKETHROUGH) {
+                            push_line(LineKind::Strikethrough, final_fg_color);
+                        }
+                        if cell.flags.contains(CellFlags::OVERLINE) {
+                            push_line(LineKind::Overline, final_fg_color);
                         }
 
                         let is_hovered_link =
@@ -639,7 +789,7 @@ impl Renderer {
                         if cell.flags.contains(CellFlags::UNDERCURL) || is_hovered_link {
                             row_undercurls.push(UndercurlInstance {
                                 position: [cell_x_pos, 0.0],
-                                color: final_fg_color,
+                                color: underline_color,
                             });
                         }
                     }
@@ -650,12 +800,12 @@ impl Renderer {
                             position: [inst.position[0], y_pos],
                             color: inst.color,
                         }));
-                    self.underline
-                        .instances
-                        .extend(row_underlines.iter().map(|inst| UnderlineInstance {
+                    for (kind, inst) in &row_lines {
+                        self.lines[*kind as usize].instances.push(LineInstance {
                             position: [inst.position[0], y_pos],
                             color: inst.color,
-                        }));
+                        });
+                    }
                     self.undercurl
                         .instances
                         .extend(row_undercurls.iter().map(|inst| UndercurlInstance {
@@ -664,19 +814,61 @@ impl Renderer {
                         }));
 
                     self.bg_cache.put(row_hash, row_bgs);
-                    self.underline_cache.put(row_hash, row_underlines);
+                    self.line_cache.put(row_hash, row_lines);
                     self.undercurl_cache.put(row_hash, row_undercurls);
                 }
             }
         }
 
-        let selection_bg_instances = self.prepare_selection_bg(selection, term, top_padding);
+        // Folded output shows only its first line, shaded to set it apart
+        let Rgb(r, g, b) = default_bg_rgb;
+        let fold_color = [
+            r.saturating_add(0x18),
+            g.saturating_add(0x18),
+            b.saturating_add(0x18),
+            255,
+        ];
+        for range in term.folded_lines() {
+            if let Some(y) = display_lines
+                .iter()
+                .position(|&line| line + 1 == range.start)
+            {
+                let y_pos = (y as f32 * self.cell_size.1) + top_padding;
+                self.bg.instances.extend((0..grid_cols).map(|x| BgInstance {
+                    position: [x as f32 * self.cell_size.0, y_pos],
+                    color: fold_color,
+                }));
+            }
+        }
+
+        let selection_bg_instances =
+            self.prepare_selection_bg(selection, term, display_lines, top_padding);
         self.bg.instances.extend_from_slice(&selection_bg_instances);
 
+        if let Some(search) = search {
+            let search_bg_instances =
+                self.prepare_search_bg(search, term, display_lines, top_padding);
+            self.bg.instances.extend_from_slice(&search_bg_instances);
+
+            // Search bar across the bottom row
+            let y_pos = (grid_rows.saturating_sub(1) as f32 * self.cell_size.1) + top_padding;
+            let Rgb(r, g, b) = default_bg_rgb;
+            self.bg.instances.extend((0..grid_cols).map(|x| BgInstance {
+                position: [x as f32 * self.cell_size.0, y_pos],
+                color: [
+                    r.saturating_add(0x30),
+                    g.saturating_add(0x30),
+                    b.saturating_add(0x30),
+                    255,
+                ],
+            }));
+        }
+
         // Send everything to the gpu
         self.bg.resize_and_write(&self.gpu.device, &self.gpu.queue);
-        self.underline
-            .resize_and_write(&self.gpu.device, &self.gpu.queue);
+        for line in &mut self.lines {
+            line.resize_and_write(&self.gpu.device, &self.gpu.queue);
+        }
         self.undercurl
             .resize_and_write(&self.gpu.device, &self.gpu.queue);
     }
@@ -691,33 +883,26 @@ impl Renderer {
     /// Helper to process selection bg
     fn prepare_selection_bg(
         &self,
-        selection: Option<((usize, usize), (usize, usize))>,
+        selection: Option<(Point, Point)>,
         term: &TerminalState,
+        display_lines: &[usize],
         top_padding: f32,
     ) -> Vec<BgInstance> {
         let mut instances = Vec::new();
-        let (start_pos, end_pos) = match selection {
-            Some((start, end)) => (start, end),
-            None => return instances,
+        let Some((a, b)) = selection else {
+            return instances;
         };
-
-        let (start, end) =
-            if start_pos.1 < end_pos.1 || (start_pos.1 == end_pos.1 && start_pos.0 <= end_pos.0) {
-                (start_pos, end_pos)
-            } else {
-                (end_pos, start_pos)
-            };
-        let (start_col, start_row) = start;
-        let (end_col, end_row) = end;
+        let (start, end) = (a.min(b), a.max(b));
 
         let cell_size = self.cell_size;
-        let selection_color = [120, 120, 120, 128];
-
-        for y in start_row..=end_row {
-            if term.grid().get_display_row(y, term.scroll_offset).is_some() {
-                let line_start = if y == start_row { start_col } else { 0 };
-                let line_end = if y == end_row {
-                    end_col
+        let ConfigColor(r, g, b) = self.config.colors.selection;
+        let selection_color = [r, g, b, 128];
+
+        for (y, &line) in display_lines.iter().enumerate() {
+            if (start.line..=end.line).contains(&line) {
+                let line_start = if line == start.line { start.col } else { 0 };
+                let line_end = if line == end.line {
+                    end.col
                 } else {
                     term.grid().cols
                 };
@@ -737,6 +922,89 @@ impl Renderer {
         instances
     }
 
+    /// Helper to highlight search matches in view, the current one brighter
+    fn prepare_search_bg(
+        &self,
+        search: &SearchBar,
+        term: &TerminalState,
+        display_lines: &[usize],
+        top_padding: f32,
+    ) -> Vec<BgInstance> {
+        let mut instances = Vec::new();
+        let Some(regex) = search.regex() else {
+            return instances;
+        };
+
+        let grid = term.grid();
+
+        let cell_size = self.cell_size;
+        let ConfigColor(r, g, b) = self.config.colors.search_match;
+        let match_color = [r, g, b, 96];
+        let ConfigColor(r, g, b) = self.config.colors.search_current;
+        let current_color = [r, g, b, 176];
+
+        // Lines are searched one at a time, since folds can leave gaps between them
+        for (y, &line) in display_lines.iter().enumerate() {
+            for found in grid.search_lines(regex, line..line + 1) {
+                let color = if search.current == Some(found) {
+                    current_color
+                } else {
+                    match_color
+                };
+
+                let line_start = if line == found.start.line {
+                    found.start.col
+                } else {
+                    0
+                };
+                let line_end = if line == found.end.line {
+                    found.end.col
+                } else {
+                    grid.cols - 1
+                };
+
+                for x in line_start..=line_end {
+                    instances.push(BgInstance {
+                        position: [
+                            x as f32 * cell_size.0,
+                            (y as f32 * cell_size.1) + top_padding,
+                        ],
+                        color,
+                    });
+                }
+            }
+        }
+
+        instances
+    }
+
+    /// Lay out the search bar's text
+    fn prepare_search_bar_text(&mut self, font_system: &mut FontSystem, search: &SearchBar) {
+        let mut text = format!("Search: {}", search.query);
+        if search.regex().is_some() && search.current.is_none() {
+            text.push_str("  (no matches)");
+        }
+
+        let ConfigColor(r, g, b) = self.config.colors.foreground;
+        let attrs = Attrs::new()
+            .family(Family::Monospace)
+            .color(glyphon::Color::rgb(r, g, b));
+
+        let buffer = self.search_bar_buffer.get_or_insert_with(|| {
+            Buffer::new(
+                font_system,
+                Metrics::new(self.config.font_size, self.cell_size.1),
+            )
+        });
+        buffer.set_size(
+            font_system,
+            Some(self.gpu.config.width as f32),
+            Some(self.cell_size.1),
+        );
+        buffer.set_text(font_system, &text, &attrs, Shaping::Advanced);
+        buffer.shape_until_scroll(font_system, false);
+    }
+
     /// Current pixel dimensions of the swap-chain surface
     pub fn surface_size(&self) -> (u32, u32) {
         (self.gpu.config.width, self.gpu.config.height)
@@ -756,6 +1024,17 @@ impl Renderer {
     }
 }
 
+/// Surface clear color for background `rgb`, converted to linear light
+fn clear_color(rgb: Rgb, opacity: f32) -> wgpu::Color {
+    let srgb_to_linear_f64 = |c: u8| (c as f64 / 255.0).powf(2.2);
+    wgpu::Color {
+        r: srgb_to_linear_f64(rgb.0),
+        g: srgb_to_linear_f64(rgb.1),
+        b: srgb_to_linear_f64(rgb.2),
+        a: opacity as f64,
+    }
+}
+
 impl GpuState {
     async fn new(window: &Window, _config: &Config) -> Self {
         let instance = Instance::default();
@@ -976,27 +1255,33 @@ impl UndercurlRenderer {
     }
 }
 
-impl UnderlineRenderer {
-    fn new(device: &Device, format: TextureFormat, globals_layout: &BindGroupLayout) -> Self {
+impl LineRenderer {
+    fn new(
+        device: &Device,
+        format: TextureFormat,
+        globals_layout: &BindGroupLayout,
+        kind: LineKind,
+    ) -> Self {
+        let (label, source) = kind.shader();
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("underline.wgsl"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/underline.wgsl").into()),
+            label: Some(label),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
         });
 
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("Underline Pipeline Layout"),
+            label: Some("Line Pipeline Layout"),
             bind_group_layouts: &[globals_layout],
             push_constant_ranges: &[],
         });
 
         let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Underline Pipeline"),
+            label: Some(label),
             layout: Some(&pipeline_layout),
             vertex: wgpu::VertexState {
                 module: &shader,
                 entry_point: Some("vs_main"),
                 compilation_options: wgpu::PipelineCompilationOptions::default(),
-                buffers: &[BgVertex::desc(), UnderlineInstance::desc()],
+                buffers: &[BgVertex::desc(), LineInstance::desc()],
             },
             fragment: Some(wgpu::FragmentState {
                 module: &shader,
@@ -1017,13 +1302,14 @@ impl UnderlineRenderer {
 
         let initial_capacity = 2_000;
         let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Underline Instance Buffer"),
-            size: std::mem::size_of::<UnderlineInstance>() as u64 * initial_capacity,
+            label: Some("Line Instance Buffer"),
+            size: std::mem::size_of::<LineInstance>() as u64 * initial_capacity,
             usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
             mapped_at_creation: false,
         });
 
         Self {
+            kind,
             pipeline,
             instances: Vec::with_capacity(initial_capacity as usize),
             instance_buffer,
@@ -1037,8 +1323,8 @@ impl UnderlineRenderer {
         if required_instances > self.instance_capacity {
             self.instance_capacity = (required_instances as f32 * 1.5) as u64;
             self.instance_buffer = device.create_buffer(&BufferDescriptor {
-                label: Some("Underline Instance Buffer (Resized)"),
-                size: std::mem::size_of::<UnderlineInstance>() as u64 * self.instance_capacity,
+                label: Some("Line Instance Buffer (Resized)"),
+                size: std::mem::size_of::<LineInstance>() as u64 * self.instance_capacity,
                 usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
                 mapped_at_creation: false,
             });
@@ -1052,4 +1338,12 @@ impl UnderlineRenderer {
             );
         }
     }
+
+    fn draw(&self, rpass: &mut RenderPass) {
+        if !self.instances.is_empty() {
+            rpass.set_pipeline(&self.pipeline);
+            rpass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+            rpass.draw(0..BG_VERTICES.len() as u32, 0..self.instances.len() as u32);
+        }
+    }
 }
@@ -0,0 +1,62 @@
+use screen_grid::{Direction, Point, RegexSearch, SearchMatch};
+
+use crate::terminal::TerminalState;
+
+/// How far back in history a match is looked for while the query is being
+/// typed. Stepping through matches explicitly searches all of history
+const MAX_SEARCH_WHILE_TYPING: usize = 1000;
+
+/// State of the search bar while it's open
+#[derive(Default)]
+pub struct SearchBar {
+    pub query: String,
+    regex: Option<RegexSearch>,
+    /// The match the viewport was last moved to
+    pub current: Option<SearchMatch>,
+}
+
+impl SearchBar {
+    pub fn regex(&self) -> Option<&RegexSearch> {
+        self.regex.as_ref()
+    }
+
+    /// Replace the query and jump to its newest match near the bottom of history.
+    /// Text that isn't a valid regex yet is searched for literally
+    pub fn set_query(&mut self, query: String, term: &mut TerminalState) {
+        self.regex = (!query.is_empty())
+            .then(|| RegexSearch::new(&query).unwrap_or_else(|_| RegexSearch::literal(&query)));
+        self.query = query;
+        self.current = None;
+
+        self.find(term, Direction::Backward, Some(MAX_SEARCH_WHILE_TYPING));
+    }
+
+    /// Move to the next match in `direction` and scroll it into view
+    pub fn step(&mut self, term: &mut TerminalState, direction: Direction) {
+        self.find(term, direction, None);
+    }
+
+    fn find(&mut self, term: &mut TerminalState, direction: Direction, max_lines: Option<usize>) {
+        term.is_dirty = true;
+
+        let Some(regex) = &self.regex else {
+            return;
+        };
+
+        let grid = term.grid();
+        let origin = self.current.map_or(
+            Point {
+                line: grid.total_lines(),
+                col: 0,
+            },
+            |m| m.start,
+        );
+
+        self.current = grid.search(regex, origin, direction, max_lines);
+        if let Some(found) = self.current {
+            // The search bar covers the bottom row
+            let visible_rows = grid.rows.saturating_sub(1);
+            term.scroll_to_line(found.start.line, visible_rows);
+        }
+    }
+}
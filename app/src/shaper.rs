@@ -3,7 +3,7 @@ use std::{
     sync::Arc,
 };
 
-use crate::{config::Config, terminal::TerminalState};
+use crate::{config::Config, palette::Palette, terminal::TerminalState};
 use glyphon::{
     Attrs, Buffer, Family, FontSystem, Metrics, Shaping, Style, Weight,
     fontdb::{self, Database},
@@ -56,18 +56,38 @@ impl Shaper {
         let mut lines_shaped = 0;
 
         let cursor_visible = term.cursor_visible;
-        let (cur_y, cur_x, grid_cols, scrollback_len) = {
+        let display_lines = term.display_lines();
+        // Rows are shaped while the grid is borrowed, so take a copy to resolve colors with
+        let palette = term.palette.clone();
+        let (cur_y, cur_x, grid_cols) = {
             let grid = term.grid();
-            (grid.cur_y, grid.cur_x, grid.cols, grid.scrollback_len())
+            (grid.cur_y, grid.cur_x, grid.cols)
         };
 
         let grid_mut = term.grid_mut();
+        grid_mut.load_lines(&display_lines);
 
-        let viewport_start = scrollback_len;
-        let buffer_len = grid_mut.lines.len();
+        for (y, row) in grid_mut.lines.iter_mut().enumerate() {
+            if row.is_dirty {
+                if lines_shaped >= line_budget {
+                    return true;
+                }
 
-        for y in viewport_start..buffer_len {
-            if let Some(row) = grid_mut.lines.get_mut(y) {
+                self.shape_single_row(
+                    font_system,
+                    fallback_cache,
+                    row,
+                    &palette,
+                    grid_cols,
+                    cursor_visible && y == cur_y,
+                    cur_x,
+                );
+                lines_shaped += 1;
+            }
+        }
+
+        if lines_shaped < line_budget {
+            for row in grid_mut.scrollback.view_mut() {
                 if row.is_dirty {
                     if lines_shaped >= line_budget {
                         return true;
@@ -77,58 +97,30 @@ impl Shaper {
                         font_system,
                         fallback_cache,
                         row,
+                        &palette,
                         grid_cols,
-                        cursor_visible,
-                        scrollback_len,
-                        cur_y,
+                        false,
                         cur_x,
-                        y,
                     );
                     lines_shaped += 1;
                 }
             }
         }
 
-        if lines_shaped < line_budget {
-            for y in (0..viewport_start).rev() {
-                if let Some(row) = grid_mut.lines.get_mut(y) {
-                    if row.is_dirty {
-                        if lines_shaped >= line_budget {
-                            return true;
-                        }
-
-                        self.shape_single_row(
-                            font_system,
-                            fallback_cache,
-                            row,
-                            grid_cols,
-                            cursor_visible,
-                            scrollback_len,
-                            cur_y,
-                            cur_x,
-                            y,
-                        );
-                        lines_shaped += 1;
-                    }
-                }
-            }
-        }
-
         false
     }
 
     /// Helper function to contain the logic for shaping one row.
+    #[allow(clippy::too_many_arguments)]
     fn shape_single_row(
         &mut self,
         font_system: &mut FontSystem,
         fallback_cache: &mut HashMap<char, bool>,
         row: &mut Row,
+        palette: &Palette,
         grid_cols: usize,
-        cursor_visible: bool,
-        scrollback_len: usize,
-        term_cur_y: usize,
+        is_cursor_on_this_line: bool,
         term_cur_x: usize,
-        y: usize,
     ) {
         let main_font_id = {
             let query = fontdb::Query {
@@ -152,7 +144,7 @@ impl Shaper {
                     .db()
                     .with_face_data(id, |data, index| {
                         glyphon::cosmic_text::ttf_parser::Face::parse(data, index)
-                            .map_or(false, |f| f.glyph_index(c).is_some())
+                            .is_ok_and(|f| f.glyph_index(c).is_some())
                     })
                     .unwrap_or(false);
 
@@ -176,6 +168,9 @@ impl Shaper {
             Some(self.cell_size.1),
         );
 
+        // Snap fallback glyphs to whole cells so wide glyphs span exactly two columns
+        buffer.set_monospace_width(font_system, Some(self.cell_size.0));
+
         buffer.set_text(
             font_system,
             &line_text,
@@ -184,7 +179,6 @@ impl Shaper {
         );
 
         let mut attrs_list = glyphon::AttrsList::new(&self.default_attrs);
-        let is_cursor_on_this_line = cursor_visible && y == (scrollback_len + term_cur_y);
 
         if !row.cells.is_empty() {
             let mut run_start_byte = 0;
@@ -193,8 +187,13 @@ impl Shaper {
             let mut current_byte = 0;
 
             for (i, cell) in row.cells.iter().enumerate() {
+                // The glyph in the preceding cell already covers its spacer
+                if cell.flags.contains(CellFlags::WIDE_CHAR_SPACER) {
+                    continue;
+                }
+
                 let is_cursor = is_cursor_on_this_line && i == term_cur_x;
-                let char_len = cell.ch.len_utf8();
+                let char_len = cell.grapheme_len();
 
                 let current_char_needs_fallback =
                     fallback_cache.get(&cell.ch).copied().unwrap_or(false);
@@ -215,12 +214,11 @@ impl Shaper {
                     if run_end_byte > run_start_byte {
                         let fg = if run_start_cursor {
                             // If cursor is this letter, use the cursor_text color
-                            let (r, g, b) = self.config.colors.cursor_text;
-                            screen_grid::Rgb(r, g, b)
+                            self.config.colors.cursor_text.into()
                         } else {
                             // Otherwise, use the normal foreground color, handling inverse
-                            let mut fg = run_start_cell.fg;
-                            let mut bg = run_start_cell.bg;
+                            let mut fg = palette.fg(run_start_cell.fg);
+                            let mut bg = palette.bg(run_start_cell.bg);
                             if run_start_cell.flags.contains(CellFlags::INVERSE) {
                                 std::mem::swap(&mut fg, &mut bg);
                             }
@@ -233,7 +231,13 @@ impl Shaper {
                         } else {
                             self.default_attrs.clone()
                         };
-                        attrs = attrs.color(glyphon::Color::rgba(fg.0, fg.1, fg.2, 0xFF));
+                        // Concealed text keeps its place in the line but isn't drawn
+                        let alpha = if run_start_cell.flags.contains(CellFlags::HIDDEN) {
+                            0
+                        } else {
+                            0xFF
+                        };
+                        attrs = attrs.color(glyphon::Color::rgba(fg.0, fg.1, fg.2, alpha));
                         if run_start_cell.flags.contains(CellFlags::ITALIC) {
                             attrs = attrs.style(Style::Italic);
                         }
@@ -253,12 +257,11 @@ impl Shaper {
             if run_end_byte > run_start_byte {
                 let fg = if run_start_cursor {
                     // If cursor is this letter, use the cursor_text color
-                    let (r, g, b) = self.config.colors.cursor_text;
-                    screen_grid::Rgb(r, g, b)
+                    self.config.colors.cursor_text.into()
                 } else {
                     // Otherwise, use the normal foreground color, handling inverse
-                    let mut fg = run_start_cell.fg;
-                    let mut bg = run_start_cell.bg;
+                    let mut fg = palette.fg(run_start_cell.fg);
+                    let mut bg = palette.bg(run_start_cell.bg);
                     if run_start_cell.flags.contains(CellFlags::INVERSE) {
                         std::mem::swap(&mut fg, &mut bg);
                     }
@@ -274,7 +277,12 @@ impl Shaper {
                 } else {
                     self.default_attrs.clone()
                 };
-                attrs = attrs.color(glyphon::Color::rgba(fg.0, fg.1, fg.2, 0xFF));
+                let alpha = if run_start_cell.flags.contains(CellFlags::HIDDEN) {
+                    0
+                } else {
+                    0xFF
+                };
+                attrs = attrs.color(glyphon::Color::rgba(fg.0, fg.1, fg.2, alpha));
                 if run_start_cell.flags.contains(CellFlags::ITALIC) {
                     attrs = attrs.style(Style::Italic);
                 }
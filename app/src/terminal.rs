@@ -1,9 +1,25 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io::Write,
+    ops::Range,
+    path::PathBuf,
+    sync::Arc,
+    time::Instant,
+};
 
-use screen_grid::{CellFlags, Rgb, ScreenGrid};
+use screen_grid::{CellFlags, Color, Direction, Point, PromptMark, Rgb, ScreenGrid, Scrollback};
 use vte::Parser;
+use winit::event::KeyEvent;
+use winit::keyboard::ModifiersState;
 
+use crate::clipboard::ClipboardRequest;
+use crate::commands::{self, CommandRecord};
 use crate::config::Config;
+use crate::cwd;
+use crate::keyboard::{KeyModes, KittyFlags, KittyStack};
+use crate::mouse::{MouseEncoding, MouseMode, MouseReporting};
+use crate::notify::Notification;
+use crate::palette::{self, BACKGROUND, CURSOR, FOREGROUND, Palette};
 
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
 pub enum ActiveScreen {
@@ -11,41 +27,198 @@ pub enum ActiveScreen {
     Alternate,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Default)]
 struct Attrs {
-    fg: Rgb,
-    bg: Rgb,
+    fg: Color,
+    bg: Color,
     flags: CellFlags,
+    underline_color: Option<Color>,
 }
 
-impl Attrs {
-    fn from_config(config: &Config) -> Self {
-        Self {
-            fg: Rgb(
-                config.colors.foreground.0,
-                config.colors.foreground.1,
-                config.colors.foreground.2,
-            ),
-            bg: Rgb(
-                config.colors.background.0,
-                config.colors.background.1,
-                config.colors.background.2,
-            ),
-            flags: CellFlags::empty(),
+/// Read the color of an SGR 38, 48 or 58 whose first parameter is `p`.
+///
+/// Colon forms (`38:5:N`, `38:2::R:G:B`, `38:2:R:G:B`) carry the color as
+/// subparameters of `p`. The semicolon forms (`38;5;N`, `38;2;R;G;B`) take it
+/// from the parameters that follow, which are consumed from `rest`
+fn extended_color<'p>(p: &[u16], rest: &mut impl Iterator<Item = &'p [u16]>) -> Option<Color> {
+    let rgb = |c: &[u16]| Color::Rgb(Rgb(c[0] as u8, c[1] as u8, c[2] as u8));
+
+    if p.len() > 1 {
+        return match p[1] {
+            5 => p.get(2).map(|&idx| Color::Indexed(idx as u8)),
+            // With a color space id: `38:2:<id>:R:G:B`
+            2 if p.len() >= 6 => Some(rgb(&p[3..6])),
+            2 if p.len() == 5 => Some(rgb(&p[2..5])),
+            _ => None,
+        };
+    }
+
+    match rest.next()?[0] {
+        5 => rest.next().map(|idx| Color::Indexed(idx[0] as u8)),
+        2 => {
+            let (r, g, b) = (rest.next()?, rest.next()?, rest.next()?);
+            Some(rgb(&[r[0], g[0], b[0]]))
+        }
+        _ => None,
+    }
+}
+
+/// Character sets that can be designated into G0-G3
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+enum Charset {
+    #[default]
+    Ascii,
+    /// Line drawing and symbols in place of lowercase letters
+    DecSpecialGraphics,
+    /// ASCII with `#` replaced by `£`
+    Uk,
+}
+
+impl Charset {
+    fn map(self, c: char) -> char {
+        match self {
+            Charset::Ascii => c,
+            Charset::Uk => match c {
+                '#' => '£',
+                _ => c,
+            },
+            Charset::DecSpecialGraphics => match c {
+                '_' => ' ',
+                '`' => '◆',
+                'a' => '▒',
+                'b' => '␉',
+                'c' => '␌',
+                'd' => '␍',
+                'e' => '␊',
+                'f' => '°',
+                'g' => '±',
+                'h' => '␤',
+                'i' => '␋',
+                'j' => '┘',
+                'k' => '┐',
+                'l' => '┌',
+                'm' => '└',
+                'n' => '┼',
+                'o' => '⎺',
+                'p' => '⎻',
+                'q' => '─',
+                'r' => '⎼',
+                's' => '⎽',
+                't' => '├',
+                'u' => '┤',
+                'v' => '┴',
+                'w' => '┬',
+                'x' => '│',
+                'y' => '≤',
+                'z' => '≥',
+                '{' => 'π',
+                '|' => '≠',
+                '}' => '£',
+                '~' => '·',
+                _ => c,
+            },
+        }
+    }
+}
+
+/// G0-G3 charset designations and which one is shifted in
+#[derive(Clone, Copy, Default)]
+struct Charsets {
+    slots: [Charset; 4],
+    /// Slot used by locking shifts (SI, SO, LS2, LS3)
+    active: usize,
+    /// Slot used for the next character only (SS2, SS3)
+    single_shift: Option<usize>,
+}
+
+impl Charsets {
+    /// Translate a printed character through the active charset
+    fn map(&mut self, c: char) -> char {
+        let slot = self.single_shift.take().unwrap_or(self.active);
+        self.slots[slot].map(c)
+    }
+}
+
+/// Max entries kept by the XTWINOPS title stack. Pushing past this forgets the oldest
+const TITLE_STACK_LIMIT: usize = 10;
+
+/// Window title and icon name set by the program (OSC 0, 1 and 2)
+#[derive(Debug, Clone, Default)]
+pub struct Titles {
+    pub title: String,
+    pub icon_name: String,
+    /// Saved by CSI 22 t. Either part may be missing if only the other was saved
+    stack: Vec<(Option<String>, Option<String>)>,
+}
+
+impl Titles {
+    /// XTWINOPS 22 - Save the icon name and title (0), icon name only (1) or title only (2)
+    fn push(&mut self, which: usize) {
+        let entry = (
+            (which != 1).then(|| self.title.clone()),
+            (which != 2).then(|| self.icon_name.clone()),
+        );
+
+        if self.stack.len() == TITLE_STACK_LIMIT {
+            self.stack.remove(0);
         }
+        self.stack.push(entry);
     }
+
+    /// XTWINOPS 23 - Restore what the last push saved, limited to `which` like `push`
+    fn pop(&mut self, which: usize) {
+        let Some((title, icon_name)) = self.stack.pop() else {
+            return;
+        };
+
+        if which != 1
+            && let Some(title) = title
+        {
+            self.title = title;
+        }
+        if which != 2
+            && let Some(icon_name) = icon_name
+        {
+            self.icon_name = icon_name;
+        }
+    }
+}
+
+/// Cursor state stored by DECSC and brought back by DECRC
+#[derive(Clone, Copy)]
+struct SavedCursor {
+    x: usize,
+    y: usize,
+    attrs: Attrs,
+    charsets: Charsets,
 }
 
 struct VtePerformer<'a> {
     normal_grid: &'a mut ScreenGrid,
     alternate_grid: &'a mut ScreenGrid,
     active_screen: &'a mut ActiveScreen,
+    normal_saved_cursor: &'a mut Option<SavedCursor>,
+    alternate_saved_cursor: &'a mut Option<SavedCursor>,
 
     attrs: &'a mut Attrs,
+    palette: &'a mut Palette,
+    charsets: &'a mut Charsets,
     cursor_visible: &'a mut bool,
+    mouse: &'a mut MouseReporting,
+    key_modes: &'a mut KeyModes,
+    normal_kitty: &'a mut KittyStack,
+    alternate_kitty: &'a mut KittyStack,
+    bracketed_paste: &'a mut bool,
+    titles: &'a mut Titles,
+    cwd: &'a mut Option<PathBuf>,
     current_link_id: &'a mut Option<u32>,
     links: &'a mut HashMap<u32, String>,
     next_link_id: &'a mut u32,
+    replies: &'a mut Vec<u8>,
+    clipboard_requests: &'a mut Vec<ClipboardRequest>,
+    notifications: &'a mut Vec<Notification>,
+    command_started: &'a mut Option<Instant>,
+    focused: bool,
     config: Arc<Config>,
 }
 
@@ -56,51 +229,131 @@ impl<'a> VtePerformer<'a> {
             ActiveScreen::Alternate => self.alternate_grid,
         }
     }
-}
 
-impl<'a> vte::Perform for VtePerformer<'a> {
-    fn print(&mut self, c: char) {
-        let attrs = *self.attrs;
-        let link_id = *self.current_link_id;
+    /// Each screen has its own saved cursor
+    fn saved_cursor_mut(&mut self) -> &mut Option<SavedCursor> {
+        match *self.active_screen {
+            ActiveScreen::Normal => self.normal_saved_cursor,
+            ActiveScreen::Alternate => self.alternate_saved_cursor,
+        }
+    }
 
-        self.grid_mut()
-            .put_char_ex(c, attrs.fg, attrs.bg, attrs.flags, link_id);
+    /// Each screen has its own kitty keyboard flags
+    fn kitty_mut(&mut self) -> &mut KittyStack {
+        match *self.active_screen {
+            ActiveScreen::Normal => self.normal_kitty,
+            ActiveScreen::Alternate => self.alternate_kitty,
+        }
     }
 
-    fn execute(&mut self, byte: u8) {
+    /// DECSC - Save the cursor position and pen
+    fn save_cursor(&mut self) {
         let grid = self.grid_mut();
-        if let Some(row) = grid.visible_row_mut(grid.cur_y) {
-            row.is_dirty = true;
-        }
+        let (x, y) = (grid.cur_x, grid.cur_y);
+        let attrs = *self.attrs;
+        let charsets = *self.charsets;
 
-        match byte {
-            // Newline and Index move down one line
-            b'\n' | 0x84 => grid.line_feed(),
+        *self.saved_cursor_mut() = Some(SavedCursor {
+            x,
+            y,
+            attrs,
+            charsets,
+        });
+    }
 
-            // NEL moves down one line AND to column 0
-            0x85 => {
-                grid.line_feed();
-                grid.cur_x = 0;
+    /// DECRC - Restore what DECSC saved, or go home with the default pen if nothing was saved
+    fn restore_cursor(&mut self) {
+        let saved = self.saved_cursor_mut().unwrap_or(SavedCursor {
+            x: 0,
+            y: 0,
+            attrs: Attrs::default(),
+            charsets: Charsets::default(),
+        });
+
+        *self.attrs = saved.attrs;
+        *self.charsets = saved.charsets;
+        self.grid_mut().set_cursor_pos(saved.x, saved.y);
+    }
+
+    /// DECSET / DECRST for a single mode
+    fn set_private_mode(&mut self, mode: u16, enable: bool) {
+        match mode {
+            // DECCKM - Application cursor keys
+            1 => self.key_modes.app_cursor = enable,
+            // DECTCEM - Show / hide the cursor
+            25 => {
+                *self.cursor_visible = enable;
+                let grid = self.grid_mut();
+                if let Some(row) = grid.visible_row_mut(grid.cur_y) {
+                    row.is_dirty = true;
+                }
             }
+            // Mouse tracking. Turning a mode off only matters if it's the current one
+            9 | 1000 | 1002 | 1003 => {
+                let mouse_mode = match mode {
+                    9 => MouseMode::X10,
+                    1000 => MouseMode::Click,
+                    1002 => MouseMode::Drag,
+                    _ => MouseMode::Motion,
+                };
 
-            b'\r' => grid.cur_x = 0,
-            b'\x08' => {
-                grid.cur_x = grid.cur_x.saturating_sub(1);
+                if enable {
+                    self.mouse.mode = mouse_mode;
+                } else if self.mouse.mode == mouse_mode {
+                    self.mouse.mode = MouseMode::Off;
+                }
             }
-            _ => (),
+            // Bracketed paste
+            2004 => *self.bracketed_paste = enable,
+            // Mouse report encoding
+            1005 | 1006 | 1016 => {
+                let encoding = match mode {
+                    1005 => MouseEncoding::Utf8,
+                    1006 => MouseEncoding::Sgr,
+                    _ => MouseEncoding::SgrPixels,
+                };
+
+                if enable {
+                    self.mouse.encoding = encoding;
+                } else if self.mouse.encoding == encoding {
+                    self.mouse.encoding = MouseEncoding::Default;
+                }
+            }
+            _ => {}
         }
+    }
 
-        if let Some(row) = grid.visible_row_mut(grid.cur_y) {
-            row.is_dirty = true;
+    /// Set palette slot `slot`, or reset it to its configured color if `color` is `None`
+    fn set_palette_color(&mut self, slot: usize, color: Option<Rgb>) {
+        match color {
+            Some(color) => self.palette.set(slot, color),
+            None => self.palette.reset(slot),
         }
+        self.palette_changed();
     }
 
-    fn osc_dispatch(&mut self, params: &[&[u8]], _bell_terminated: bool) {
-        // We only care about OSC 8 for hyperlinks (for now?)
-        if params.get(0) != Some(&&b"8"[..]) {
-            return;
+    /// Redraw both screens, since any cell may use a color that changed
+    fn palette_changed(&mut self) {
+        self.normal_grid.mark_all_dirty();
+        self.alternate_grid.mark_all_dirty();
+    }
+
+    /// Apply a color spec from OSC 4 or 10-12 to `slot`, or answer it if it's `?`.
+    /// `prefix` is what the reply starts with, e.g. `4;1` or `11`
+    fn set_or_query_color(&mut self, prefix: &str, slot: usize, spec: &[u8], terminator: &str) {
+        if spec == b"?" {
+            let color = palette::format_color(self.palette.get(slot));
+            write!(self.replies, "\x1b]{prefix};{color}{terminator}").ok();
+        } else if let Some(color) = std::str::from_utf8(spec)
+            .ok()
+            .and_then(palette::parse_color)
+        {
+            self.set_palette_color(slot, Some(color));
         }
+    }
 
+    /// OSC 8 - Start a hyperlink, or end it when the URL is empty
+    fn set_hyperlink(&mut self, params: &[&[u8]]) {
         let params_str = params.get(1).map(|p| std::str::from_utf8(p).unwrap_or(""));
         let url = params.get(2).map(|p| std::str::from_utf8(p).unwrap_or(""));
 
@@ -129,6 +382,302 @@ impl<'a> vte::Perform for VtePerformer<'a> {
         }
     }
 
+    /// OSC 133 D - Notify about a command that ran longer than
+    /// `notify_after_seconds` while the window was unfocused
+    fn command_finished(&mut self, exit_code: Option<i32>) {
+        let Some(started) = self.command_started.take() else {
+            return;
+        };
+
+        let threshold = self.config.notify_after_seconds;
+        let elapsed = started.elapsed().as_secs();
+        if self.focused || threshold == 0 || elapsed < threshold {
+            return;
+        }
+
+        // Commands are only tracked on the normal screen
+        let grid = &*self.normal_grid;
+        let title = commands::command_records(grid)
+            .pop()
+            .map(|record| record.command_line(grid))
+            .filter(|command| !command.is_empty())
+            .unwrap_or_else(|| "Command".to_string());
+        let status = match exit_code {
+            Some(0) | None => "Finished".to_string(),
+            Some(code) => format!("Failed with exit status {code}"),
+        };
+
+        self.notifications.push(Notification {
+            title,
+            body: format!("{status} after {elapsed}s"),
+        });
+    }
+
+    /// RIS - Reset to Initial State. History is kept
+    fn reset(&mut self) {
+        *self.palette = Palette::new(&self.config);
+        self.palette_changed();
+
+        *self.active_screen = ActiveScreen::Normal;
+        *self.attrs = Attrs::default();
+        *self.charsets = Charsets::default();
+        *self.cursor_visible = true;
+        *self.mouse = MouseReporting::default();
+        *self.key_modes = KeyModes::default();
+        *self.normal_kitty = KittyStack::default();
+        *self.alternate_kitty = KittyStack::default();
+        *self.bracketed_paste = false;
+        *self.current_link_id = None;
+        *self.normal_saved_cursor = None;
+        *self.alternate_saved_cursor = None;
+
+        self.normal_grid.reset();
+        self.alternate_grid.reset();
+    }
+}
+
+impl<'a> vte::Perform for VtePerformer<'a> {
+    fn print(&mut self, c: char) {
+        let c = self.charsets.map(c);
+        let attrs = *self.attrs;
+        let link_id = *self.current_link_id;
+
+        self.grid_mut().put_char_ex(
+            c,
+            attrs.fg,
+            attrs.bg,
+            attrs.flags,
+            attrs.underline_color,
+            link_id,
+        );
+    }
+
+    fn execute(&mut self, byte: u8) {
+        // Charset shifts don't touch the grid
+        match byte {
+            // SO and SI shift G1 and G0 in
+            0x0E => {
+                self.charsets.active = 1;
+                return;
+            }
+            0x0F => {
+                self.charsets.active = 0;
+                return;
+            }
+
+            // SS2 and SS3 use G2 or G3 for the next character
+            0x8E => {
+                self.charsets.single_shift = Some(2);
+                return;
+            }
+            0x8F => {
+                self.charsets.single_shift = Some(3);
+                return;
+            }
+            _ => {}
+        }
+
+        let grid = self.grid_mut();
+        if let Some(row) = grid.visible_row_mut(grid.cur_y) {
+            row.is_dirty = true;
+        }
+
+        match byte {
+            // Newline and Index move down one line
+            b'\n' | 0x84 => grid.line_feed(),
+
+            // NEL moves down one line AND to column 0
+            0x85 => {
+                grid.line_feed();
+                grid.carriage_return();
+            }
+
+            // HTS sets a tab stop at the cursor column
+            0x88 => grid.set_tab_stop(),
+
+            // RI moves up one line, scrolling down at the top of the region
+            0x8D => grid.reverse_index(),
+
+            b'\t' => grid.tab_forward(1),
+            b'\r' => grid.carriage_return(),
+            b'\x08' => grid.backspace(),
+            _ => (),
+        }
+
+        if let Some(row) = grid.visible_row_mut(grid.cur_y) {
+            row.is_dirty = true;
+        }
+    }
+
+    fn osc_dispatch(&mut self, params: &[&[u8]], bell_terminated: bool) {
+        let Some(command) = params.first().and_then(|p| std::str::from_utf8(p).ok()) else {
+            return;
+        };
+        // Replies end the same way the request did
+        let terminator = if bell_terminated { "\x07" } else { "\x1b\\" };
+
+        match command {
+            // Set icon name and title, icon name only, or title only.
+            // The text may contain `;`, which splits it into more params
+            "0" | "1" | "2" => {
+                let text = params[1..].join(&b';');
+                let text = String::from_utf8_lossy(&text).into_owned();
+
+                if command != "2" {
+                    self.titles.icon_name = text.clone();
+                }
+                if command != "1" {
+                    self.titles.title = text;
+                }
+            }
+            // Current working directory, as a `file://host/path` URI
+            "7" => {
+                let uri = params[1..].join(&b';');
+                *self.cwd = std::str::from_utf8(&uri).ok().and_then(cwd::parse_file_uri);
+            }
+            // Set or query indexed colors: `OSC 4 ; index ; spec` with any number of pairs
+            "4" => {
+                for pair in params[1..].chunks(2) {
+                    let [index, spec] = pair else {
+                        break;
+                    };
+                    let Some(index) = parse_osc_number(index).filter(|&i| i < FOREGROUND) else {
+                        continue;
+                    };
+                    self.set_or_query_color(&format!("4;{index}"), index, spec, terminator);
+                }
+            }
+            // Set or query foreground, background or cursor color.
+            // Each extra spec applies to the next color along, like xterm
+            "10" | "11" | "12" => {
+                let first: usize = command.parse().unwrap_or(10);
+                for (i, spec) in params[1..].iter().enumerate().take(13 - first) {
+                    let number = first + i;
+                    let slot = FOREGROUND + number - 10;
+                    self.set_or_query_color(&number.to_string(), slot, spec, terminator);
+                }
+            }
+            // Reset the listed indexed colors, or all of them
+            "104" => {
+                let indices: Vec<usize> = params[1..]
+                    .iter()
+                    .filter_map(|p| parse_osc_number(p))
+                    .collect();
+
+                if indices.is_empty() {
+                    self.palette.reset_indexed();
+                }
+                for index in indices.into_iter().filter(|&i| i < FOREGROUND) {
+                    self.palette.reset(index);
+                }
+                self.palette_changed();
+            }
+            // Reset foreground, background or cursor color
+            "110" => self.set_palette_color(FOREGROUND, None),
+            "111" => self.set_palette_color(BACKGROUND, None),
+            "112" => self.set_palette_color(CURSOR, None),
+            // Hyperlinks
+            "8" => self.set_hyperlink(params),
+            // Shell integration: `OSC 133 ; A`, `B`, `C`, or `D ; exit status`.
+            // Options after the letter are ignored
+            "133" => {
+                let mark = match params.get(1).and_then(|p| p.first()) {
+                    Some(b'A') => PromptMark::PromptStart,
+                    Some(b'B') => PromptMark::CommandStart,
+                    Some(b'C') => PromptMark::OutputStart,
+                    Some(b'D') => PromptMark::CommandEnd(
+                        params
+                            .get(2)
+                            .and_then(|p| std::str::from_utf8(p).ok())
+                            .and_then(|p| p.parse().ok()),
+                    ),
+                    _ => return,
+                };
+                // Commands are tracked on the normal screen, at its cursor,
+                // even while a program has the alternate screen up
+                self.normal_grid.add_mark(mark);
+
+                match mark {
+                    PromptMark::OutputStart => *self.command_started = Some(Instant::now()),
+                    PromptMark::CommandEnd(exit_code) => self.command_finished(exit_code),
+                    _ => {}
+                }
+            }
+            // Desktop notification: `OSC 9 ; message`. ConEmu's OSC 9 commands,
+            // such as progress reports, start with a number and are ignored
+            "9" => {
+                let is_conemu = params.len() > 2 && parse_osc_number(params[1]).is_some();
+                let text = String::from_utf8_lossy(&params[1..].join(&b';')).into_owned();
+
+                if !is_conemu && !text.is_empty() {
+                    self.notifications.push(Notification {
+                        title: text,
+                        body: String::new(),
+                    });
+                }
+            }
+            // Desktop notification: `OSC 777 ; notify ; title ; body`
+            "777" => {
+                if params.get(1) == Some(&&b"notify"[..])
+                    && let Some(title) = params.get(2)
+                {
+                    let body = params[3..].join(&b';');
+                    self.notifications.push(Notification {
+                        title: String::from_utf8_lossy(title).into_owned(),
+                        body: String::from_utf8_lossy(&body).into_owned(),
+                    });
+                }
+            }
+            // Set or query the clipboard
+            "52" => {
+                let policy = &self.config.clipboard;
+                if let Some(request) = ClipboardRequest::from_osc(params, policy, terminator) {
+                    self.clipboard_requests.push(request);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn esc_dispatch(&mut self, intermediates: &[u8], _ignore: bool, byte: u8) {
+        match (intermediates, byte) {
+            // DECSC - Save Cursor
+            ([], b'7') => self.save_cursor(),
+            // DECRC - Restore Cursor
+            ([], b'8') => self.restore_cursor(),
+
+            // IND, NEL, HTS and RI: 7-bit forms of the C1 controls 0x84, 0x85, 0x88 and 0x8D
+            ([], b'D' | b'E' | b'H' | b'M') => vte::Perform::execute(self, byte + 0x40),
+
+            // SS2 and SS3: 7-bit forms of the C1 controls 0x8E and 0x8F
+            ([], b'N' | b'O') => vte::Perform::execute(self, byte + 0x40),
+            // LS2 and LS3 - Locking Shift G2 or G3
+            ([], b'n') => self.charsets.active = 2,
+            ([], b'o') => self.charsets.active = 3,
+
+            // SCS - Designate a charset into G0-G3
+            ([slot @ (b'(' | b')' | b'*' | b'+')], _) => {
+                let charset = match byte {
+                    b'0' => Charset::DecSpecialGraphics,
+                    b'A' => Charset::Uk,
+                    b'B' => Charset::Ascii,
+                    _ => return,
+                };
+                self.charsets.slots[(slot - b'(') as usize] = charset;
+            }
+
+            // DECKPAM / DECKPNM - Application / normal keypad
+            ([], b'=') => self.key_modes.app_keypad = true,
+            ([], b'>') => self.key_modes.app_keypad = false,
+
+            // RIS - Reset to Initial State
+            ([], b'c') => self.reset(),
+            // DECALN - Screen Alignment Pattern
+            ([b'#'], b'8') => self.grid_mut().fill_alignment_pattern(),
+            _ => {}
+        }
+    }
+
     fn hook(&mut self, _params: &vte::Params, _intermediates: &[u8], _ignore: bool, _c: char) {
         // TODO utilize this later
     }
@@ -143,20 +692,23 @@ impl<'a> vte::Perform for VtePerformer<'a> {
         let mut params_iter = params.iter();
         let mut get_param = |default| params_iter.next().map(|p| p[0] as usize).unwrap_or(default);
 
-        if intermediates.get(0) == Some(&b'?') {
+        if intermediates.first() == Some(&b'?') {
             if let Some(p) = params.iter().next() {
                 // Check for 1049, code for alt screen with clear
                 if p[0] == 1049 {
                     match final_byte {
                         'h' => {
-                            // Enter Alternate Screen
+                            // Enter Alternate Screen, saving the cursor like DECSC
+                            self.save_cursor();
                             *self.active_screen = ActiveScreen::Alternate;
+                            *self.alternate_kitty = KittyStack::default();
                             // Clear the alternate screen before use
                             self.grid_mut().clear_all();
                         }
                         'l' => {
-                            // Leave Alternate Screen
+                            // Leave Alternate Screen and restore the saved cursor
                             *self.active_screen = ActiveScreen::Normal;
+                            self.restore_cursor();
                             // Make sure cursor is visible when returning
                             *self.cursor_visible = true;
                         }
@@ -169,25 +721,48 @@ impl<'a> vte::Perform for VtePerformer<'a> {
             }
 
             match final_byte {
-                'h' => {
-                    // DECSET - Turn mode ON
-                    if get_param(0) == 25 {
-                        *self.cursor_visible = true;
-                        let grid = self.grid_mut();
-                        if let Some(row) = grid.visible_row_mut(grid.cur_y) {
-                            row.is_dirty = true;
-                        }
+                // DECSET / DECRST - Turn modes ON / OFF
+                'h' | 'l' => {
+                    for p in params.iter() {
+                        self.set_private_mode(p[0], final_byte == 'h');
                     }
                 }
-                'l' => {
-                    // DECRST - Turn mode OFF
-                    if get_param(0) == 25 {
-                        *self.cursor_visible = false;
-                        let grid = self.grid_mut();
-                        if let Some(row) = grid.visible_row_mut(grid.cur_y) {
-                            row.is_dirty = true;
-                        }
-                    }
+                // DECXCPR - Extended Cursor Position Report
+                'n' if get_param(0) == 6 => {
+                    let grid = self.grid_mut();
+                    let (row, col) = (grid.cur_y + 1, grid.cur_x + 1);
+                    write!(self.replies, "\x1b[?{row};{col}R").ok();
+                }
+                // Kitty keyboard protocol - Query the current flags
+                'u' => {
+                    let flags = self.kitty_mut().flags().bits();
+                    write!(self.replies, "\x1b[?{flags}u").ok();
+                }
+                _ => {}
+            }
+
+            return;
+        }
+
+        if intermediates.first() == Some(&b'>') {
+            match final_byte {
+                // DA2 - Secondary Device Attributes: VT220, our version, no ROM cartridge
+                'c' if get_param(0) == 0 => {
+                    write!(self.replies, "\x1b[>1;{};0c", version_number()).ok();
+                }
+                // XTVERSION - Report the terminal's name and version
+                'q' if get_param(0) == 0 => {
+                    write!(
+                        self.replies,
+                        "\x1bP>|bnuuy {}\x1b\\",
+                        env!("CARGO_PKG_VERSION")
+                    )
+                    .ok();
+                }
+                // Kitty keyboard protocol - Push flags
+                'u' => {
+                    let flags = KittyFlags::from_bits_truncate(get_param(0) as u8);
+                    self.kitty_mut().push(flags);
                 }
                 _ => {}
             }
@@ -195,7 +770,38 @@ impl<'a> vte::Perform for VtePerformer<'a> {
             return;
         }
 
+        if final_byte == 'c' && intermediates.first() == Some(&b'=') {
+            // DA3 - Tertiary Device Attributes: report a unit ID of zero, like xterm
+            if get_param(0) == 0 {
+                self.replies.extend_from_slice(b"\x1bP!|00000000\x1b\\");
+            }
+
+            return;
+        }
+
+        if final_byte == 'u' && matches!(intermediates.first(), Some(b'<' | b'=')) {
+            if intermediates[0] == b'<' {
+                // Kitty keyboard protocol - Pop flags
+                self.kitty_mut().pop(get_param(1).max(1));
+            } else {
+                // Kitty keyboard protocol - Set flags
+                let flags = KittyFlags::from_bits_truncate(get_param(0) as u8);
+                let mode = get_param(1).max(1);
+                self.kitty_mut().set(flags, mode);
+            }
+
+            return;
+        }
+
         match final_byte {
+            't' => {
+                // XTWINOPS - Window manipulation. Only the title stack is supported
+                match (get_param(0), get_param(0)) {
+                    (22, which) => self.titles.push(which),
+                    (23, which) => self.titles.pop(which),
+                    _ => {}
+                }
+            }
             'r' => {
                 // DECSTBM - Set Scrolling Region
                 let grid = self.grid_mut();
@@ -209,7 +815,7 @@ impl<'a> vte::Perform for VtePerformer<'a> {
                     let top = params
                         .iter()
                         .nth(0)
-                        .and_then(|p| p.get(0))
+                        .and_then(|p| p.first())
                         .map(|&v| v as usize)
                         .unwrap_or(1)
                         .saturating_sub(1);
@@ -217,7 +823,7 @@ impl<'a> vte::Perform for VtePerformer<'a> {
                     let bottom = params
                         .iter()
                         .nth(1)
-                        .and_then(|p| p.get(0))
+                        .and_then(|p| p.first())
                         .map(|&v| v as usize)
                         .unwrap_or(grid.rows)
                         .saturating_sub(1);
@@ -237,98 +843,75 @@ impl<'a> vte::Perform for VtePerformer<'a> {
             'm' => {
                 // SGR - Select Graphic Rendition
                 if params.is_empty() {
-                    *self.attrs = Attrs::from_config(&self.config);
+                    *self.attrs = Attrs::default();
                     return;
                 }
 
                 let mut param_iter = params.iter();
 
                 while let Some(p) = param_iter.next() {
-                    let n = p[0] as u16;
+                    let n = p[0];
 
                     match n {
-                        0 => *self.attrs = Attrs::from_config(&self.config),
+                        0 => *self.attrs = Attrs::default(),
                         1 => self.attrs.flags.insert(CellFlags::BOLD),
                         2 => self.attrs.flags.insert(CellFlags::FAINT),
                         3 => self.attrs.flags.insert(CellFlags::ITALIC),
                         4 => {
                             // `4:x` is a Kitty/VTE extension for styled underlines
-                            self.attrs
-                                .flags
-                                .remove(CellFlags::UNDERLINE | CellFlags::UNDERCURL);
+                            self.attrs.flags.remove(CellFlags::ANY_UNDERLINE);
                             let style = if p.len() > 1 { p[1] } else { 1 };
                             match style {
-                                1 => self.attrs.flags.insert(CellFlags::UNDERLINE), // `4` or `4:1`
-                                3 => self.attrs.flags.insert(CellFlags::UNDERCURL), // `4:3`
                                 0 => {} // `4:0` is "no underline"
-                                _ => self.attrs.flags.insert(CellFlags::UNDERLINE),
+                                2 => self.attrs.flags.insert(CellFlags::DOUBLE_UNDERLINE),
+                                3 => self.attrs.flags.insert(CellFlags::UNDERCURL),
+                                4 => self.attrs.flags.insert(CellFlags::DOTTED_UNDERLINE),
+                                5 => self.attrs.flags.insert(CellFlags::DASHED_UNDERLINE),
+                                _ => self.attrs.flags.insert(CellFlags::UNDERLINE), // `4` or `4:1`
                             }
                         }
+                        // Blinking text is drawn steady, so SGR 5, 6 and 25 have nothing to do
+                        5 | 6 | 25 => {}
                         7 => self.attrs.flags.insert(CellFlags::INVERSE),
+                        8 => self.attrs.flags.insert(CellFlags::HIDDEN),
+                        9 => self.attrs.flags.insert(CellFlags::STRIKETHROUGH),
+                        21 => {
+                            self.attrs.flags.remove(CellFlags::ANY_UNDERLINE);
+                            self.attrs.flags.insert(CellFlags::DOUBLE_UNDERLINE);
+                        }
                         22 => self.attrs.flags.remove(CellFlags::BOLD | CellFlags::FAINT),
                         23 => self.attrs.flags.remove(CellFlags::ITALIC),
-                        24 => self
-                            .attrs
-                            .flags
-                            .remove(CellFlags::UNDERLINE | CellFlags::UNDERCURL),
+                        24 => self.attrs.flags.remove(CellFlags::ANY_UNDERLINE),
                         27 => self.attrs.flags.remove(CellFlags::INVERSE),
+                        28 => self.attrs.flags.remove(CellFlags::HIDDEN),
+                        29 => self.attrs.flags.remove(CellFlags::STRIKETHROUGH),
+                        53 => self.attrs.flags.insert(CellFlags::OVERLINE),
+                        55 => self.attrs.flags.remove(CellFlags::OVERLINE),
 
-                        30..=37 => self.attrs.fg = ansi_16((n - 30) as u8, false),
-                        90..=97 => self.attrs.fg = ansi_16((n - 90) as u8, true),
-                        39 => self.attrs.fg = Attrs::from_config(&self.config).fg,
+                        30..=37 => self.attrs.fg = Color::Indexed(n as u8 - 30),
+                        90..=97 => self.attrs.fg = Color::Indexed(n as u8 - 90 + 8),
+                        39 => self.attrs.fg = Color::Default,
 
-                        40..=47 => self.attrs.bg = ansi_16((n - 40) as u8, false),
-                        100..=107 => self.attrs.bg = ansi_16((n - 100) as u8, true),
-                        49 => self.attrs.bg = Attrs::from_config(&self.config).bg,
+                        40..=47 => self.attrs.bg = Color::Indexed(n as u8 - 40),
+                        100..=107 => self.attrs.bg = Color::Indexed(n as u8 - 100 + 8),
+                        49 => self.attrs.bg = Color::Default,
 
                         38 => {
-                            // Set foreground color (extended)
-                            if let Some(spec) = param_iter.next() {
-                                match spec[0] {
-                                    5 => {
-                                        // 256-color
-                                        if let Some(color_val) = param_iter.next() {
-                                            self.attrs.fg = ansi_256_to_rgb(color_val[0] as u8);
-                                        }
-                                    }
-                                    2 => {
-                                        // 24-bit True Color
-                                        if let (Some(r), Some(g), Some(b)) = (
-                                            param_iter.next(),
-                                            param_iter.next(),
-                                            param_iter.next(),
-                                        ) {
-                                            self.attrs.fg = Rgb(r[0] as u8, g[0] as u8, b[0] as u8);
-                                        }
-                                    }
-                                    _ => {}
-                                }
+                            if let Some(color) = extended_color(p, &mut param_iter) {
+                                self.attrs.fg = color;
                             }
                         }
                         48 => {
-                            // Set background color (extended)
-                            if let Some(spec) = param_iter.next() {
-                                match spec[0] {
-                                    5 => {
-                                        // 256-color
-                                        if let Some(color_val) = param_iter.next() {
-                                            self.attrs.bg = ansi_256_to_rgb(color_val[0] as u8);
-                                        }
-                                    }
-                                    2 => {
-                                        // 24-bit True Color
-                                        if let (Some(r), Some(g), Some(b)) = (
-                                            param_iter.next(),
-                                            param_iter.next(),
-                                            param_iter.next(),
-                                        ) {
-                                            self.attrs.bg = Rgb(r[0] as u8, g[0] as u8, b[0] as u8);
-                                        }
-                                    }
-                                    _ => {}
-                                }
+                            if let Some(color) = extended_color(p, &mut param_iter) {
+                                self.attrs.bg = color;
+                            }
+                        }
+                        58 => {
+                            if let Some(color) = extended_color(p, &mut param_iter) {
+                                self.attrs.underline_color = Some(color);
                             }
                         }
+                        59 => self.attrs.underline_color = None,
                         _ => {}
                     }
                 }
@@ -340,7 +923,8 @@ impl<'a> vte::Perform for VtePerformer<'a> {
                 if n == 0 {
                     n = 1;
                 } // Treat 0 as 1
-                grid.cur_y = grid.cur_y.saturating_sub(n).max(grid.scroll_top);
+                let y = grid.cur_y.saturating_sub(n).max(grid.scroll_top);
+                grid.set_cursor_pos(grid.cur_x, y);
             }
             'B' => {
                 // CUD - Cursor Down
@@ -349,7 +933,8 @@ impl<'a> vte::Perform for VtePerformer<'a> {
                 if n == 0 {
                     n = 1;
                 } // Treat 0 as 1
-                grid.cur_y = (grid.cur_y + n).min(grid.scroll_bottom);
+                let y = (grid.cur_y + n).min(grid.scroll_bottom);
+                grid.set_cursor_pos(grid.cur_x, y);
             }
             'C' => {
                 // CUF - Cursor Forward
@@ -358,7 +943,7 @@ impl<'a> vte::Perform for VtePerformer<'a> {
                 if n == 0 {
                     n = 1;
                 } // Treat 0 as 1
-                grid.cur_x = (grid.cur_x + n).min(grid.cols.saturating_sub(1));
+                grid.set_cursor_pos(grid.cur_x + n, grid.cur_y);
             }
             'D' => {
                 // CUB - Cursor Back
@@ -367,7 +952,7 @@ impl<'a> vte::Perform for VtePerformer<'a> {
                 if n == 0 {
                     n = 1;
                 } // Treat 0 as 1
-                grid.cur_x = grid.cur_x.saturating_sub(n);
+                grid.set_cursor_pos(grid.cur_x.saturating_sub(n), grid.cur_y);
             }
             'H' => {
                 // CUP - Cursor Position
@@ -404,7 +989,9 @@ impl<'a> vte::Perform for VtePerformer<'a> {
                     fg: self.attrs.fg,
                     bg: self.attrs.bg,
                     flags: screen_grid::CellFlags::empty(),
+                    underline_color: None,
                     link_id: *self.current_link_id,
+                    extra: None,
                 };
 
                 let grid = self.grid_mut();
@@ -413,14 +1000,67 @@ impl<'a> vte::Perform for VtePerformer<'a> {
                 let y = grid.cur_y;
 
                 if let Some(row) = grid.visible_row_mut(y) {
+                    if n > 0 {
+                        row.clear_wide_char_at(x, &blank_cell);
+                        row.clear_wide_char_at(x + n - 1, &blank_cell);
+                    }
+
                     for i in 0..n {
                         if x + i < row.cells.len() {
                             row.cells[x + i] = blank_cell.clone();
                         }
                     }
+                    if x + n >= row.cells.len() {
+                        row.wrapped = false;
+                    }
                     row.is_dirty = true;
                 }
             }
+            // DA1 - Primary Device Attributes: VT220 with ANSI color
+            'c' if intermediates.is_empty() && get_param(0) == 0 => {
+                self.replies.extend_from_slice(b"\x1b[?62;22c")
+            }
+            'n' if intermediates.is_empty() => {
+                // DSR - Device Status Report
+                match get_param(0) {
+                    // Operating status: OK
+                    5 => self.replies.extend_from_slice(b"\x1b[0n"),
+                    // CPR - Cursor Position Report
+                    6 => {
+                        let grid = self.grid_mut();
+                        let (row, col) = (grid.cur_y + 1, grid.cur_x + 1);
+                        write!(self.replies, "\x1b[{row};{col}R").ok();
+                    }
+                    _ => {}
+                }
+            }
+            'g' => {
+                // TBC - Tab Clear
+                let grid = self.grid_mut();
+                match get_param(0) {
+                    0 => grid.clear_tab_stop(),
+                    3 => grid.clear_all_tab_stops(),
+                    _ => {}
+                }
+            }
+            'I' => {
+                // CHT - Cursor Horizontal Forward Tabulation
+                let grid = self.grid_mut();
+                let mut n = get_param(1);
+                if n == 0 {
+                    n = 1;
+                }
+                grid.tab_forward(n);
+            }
+            'Z' => {
+                // CBT - Cursor Backward Tabulation
+                let grid = self.grid_mut();
+                let mut n = get_param(1);
+                if n == 0 {
+                    n = 1;
+                }
+                grid.tab_backward(n);
+            }
             '@' => {
                 // ICH - Insert Character
                 let grid = self.grid_mut();
@@ -462,65 +1102,102 @@ impl<'a> vte::Perform for VtePerformer<'a> {
     }
 }
 
-fn ansi_16(idx: u8, bright: bool) -> Rgb {
-    const BASE: [(u8, u8, u8); 8] = [
-        (0, 0, 0),
-        (205, 0, 0),
-        (0, 205, 0),
-        (205, 205, 0),
-        (0, 0, 238),
-        (205, 0, 205),
-        (0, 205, 205),
-        (229, 229, 229),
-    ];
-    let (r, g, b) = BASE[idx as usize];
-    if bright {
-        Rgb(
-            r.saturating_add(50u8),
-            g.saturating_add(50u8),
-            b.saturating_add(50u8),
-        )
-    } else {
-        Rgb(r, g, b)
-    }
+fn parse_osc_number(param: &[u8]) -> Option<usize> {
+    std::str::from_utf8(param).ok()?.parse().ok()
+}
+
+/// Crate version as a single number for DA2, e.g. 1.2.3 becomes 10203
+fn version_number() -> u32 {
+    let part = |s: &str| s.parse::<u32>().unwrap_or(0);
+    let major = part(env!("CARGO_PKG_VERSION_MAJOR"));
+    let minor = part(env!("CARGO_PKG_VERSION_MINOR"));
+    let patch = part(env!("CARGO_PKG_VERSION_PATCH"));
+
+    major * 10_000 + minor * 100 + patch
 }
 
 pub struct TerminalState {
     pub normal_grid: ScreenGrid,
     pub alternate_grid: ScreenGrid,
     pub active_screen: ActiveScreen,
+    normal_saved_cursor: Option<SavedCursor>,
+    alternate_saved_cursor: Option<SavedCursor>,
 
     parser: Parser,
     attrs: Attrs,
+    pub palette: Palette,
+    charsets: Charsets,
     pub scroll_offset: usize,
     pub cursor_visible: bool,
+    pub mouse: MouseReporting,
+    pub key_modes: KeyModes,
+    normal_kitty: KittyStack,
+    alternate_kitty: KittyStack,
+    pub bracketed_paste: bool,
+    pub titles: Titles,
+    /// Working directory reported by the shell with OSC 7
+    pub cwd: Option<PathBuf>,
     config: Arc<Config>,
     pub links: HashMap<u32, String>,
     next_link_id: u32,
     current_link_id: Option<u32>,
+    /// Bytes to send back to the PTY, in answer to queries
+    replies: Vec<u8>,
+    /// OSC 52 requests for `App` to carry out
+    clipboard_requests: Vec<ClipboardRequest>,
+    /// Notifications for `App` to show
+    notifications: Vec<Notification>,
+    /// When the running command started, going by OSC 133
+    command_started: Option<Instant>,
+    /// Whether the window has focus, set by `App`
+    pub focused: bool,
+    /// Folded output blocks, by the line their output starts on
+    /// counted from the first line ever added to history
+    folds: HashSet<usize>,
     pub is_dirty: bool,
 }
 
 impl TerminalState {
     pub fn new(cols: usize, rows: usize, config: Arc<Config>) -> Self {
-        let default_attrs = Attrs::from_config(&config);
-        let default_fg = default_attrs.fg;
-        let default_bg = default_attrs.bg;
+        let palette = Palette::new(&config);
 
-        let normal_grid = ScreenGrid::new(cols, rows, 10_000, default_fg, default_bg);
-        let alternate_grid = ScreenGrid::new(cols, rows, 0, default_fg, default_bg);
+        let scrollback = if config.scrollback_unlimited {
+            Scrollback::unlimited(config.scrollback_bytes)
+        } else {
+            Scrollback::new(config.scrollback_bytes)
+        };
+
+        let normal_grid = ScreenGrid::new(cols, rows, scrollback);
+        let alternate_grid = ScreenGrid::new(cols, rows, Scrollback::new(0));
 
         Self {
             normal_grid,
             alternate_grid,
             active_screen: ActiveScreen::Normal,
+            normal_saved_cursor: None,
+            alternate_saved_cursor: None,
             parser: Parser::new(),
-            attrs: default_attrs,
+            attrs: Attrs::default(),
+            palette,
+            charsets: Charsets::default(),
             scroll_offset: 0,
             cursor_visible: true,
+            mouse: MouseReporting::default(),
+            key_modes: KeyModes::default(),
+            normal_kitty: KittyStack::default(),
+            alternate_kitty: KittyStack::default(),
+            bracketed_paste: false,
+            titles: Titles::default(),
+            cwd: None,
             links: HashMap::new(),
             next_link_id: 1,
             current_link_id: None,
+            replies: Vec::new(),
+            clipboard_requests: Vec::new(),
+            notifications: Vec::new(),
+            command_started: None,
+            focused: true,
+            folds: HashSet::new(),
             config,
             is_dirty: true,
         }
@@ -540,6 +1217,24 @@ impl TerminalState {
         }
     }
 
+    /// Resize both screens. Folds are dropped, as re-wrapping moves their lines
+    pub fn resize(&mut self, cols: usize, rows: usize) {
+        self.normal_grid.resize(cols, rows);
+        self.alternate_grid.resize(cols, rows);
+        self.folds.clear();
+        self.is_dirty = true;
+    }
+
+    /// Switch to a reloaded config. The palette is rebuilt from its colors,
+    /// dropping any the program set, and everything is redrawn with it
+    pub fn set_config(&mut self, config: Arc<Config>) {
+        self.palette = Palette::new(&config);
+        self.config = config;
+        self.normal_grid.mark_all_dirty();
+        self.alternate_grid.mark_all_dirty();
+        self.is_dirty = true;
+    }
+
     pub fn scroll_viewport(&mut self, delta: i32) {
         if self.active_screen == ActiveScreen::Alternate {
             return;
@@ -555,6 +1250,170 @@ impl TerminalState {
         }
     }
 
+    /// Scroll the viewport so that `line`, counted from the oldest history
+    /// line, is within the top `visible_rows` rows. Lines already there
+    /// don't move the viewport
+    pub fn scroll_to_line(&mut self, line: usize, visible_rows: usize) {
+        if self.active_screen == ActiveScreen::Alternate {
+            return;
+        }
+
+        let grid = &self.normal_grid;
+        let scrollback_len = grid.scrollback_len();
+        let top = scrollback_len - self.scroll_offset.min(scrollback_len);
+        let visible_rows = visible_rows.clamp(1, grid.rows);
+
+        if (top..top + visible_rows).contains(&line) {
+            return;
+        }
+
+        // Center the line where possible
+        let new_top = line.saturating_sub(visible_rows / 2).min(scrollback_len);
+        self.scroll_offset = scrollback_len - new_top;
+        self.is_dirty = true;
+    }
+
+    /// Scroll the viewport so the previous or next prompt is at the top
+    pub fn scroll_to_prompt(&mut self, direction: Direction) {
+        if self.active_screen == ActiveScreen::Alternate {
+            return;
+        }
+
+        let grid = &self.normal_grid;
+        let scrollback_len = grid.scrollback_len();
+        let top = scrollback_len - self.scroll_offset.min(scrollback_len);
+
+        let target = match direction {
+            Direction::Backward => grid.previous_prompt(top),
+            Direction::Forward => grid.next_prompt(top),
+        };
+
+        if let Some(line) = target {
+            self.scroll_viewport(line as i32 - top as i32);
+        }
+    }
+
+    /// Commands run at the shell prompt, oldest first, from OSC 133 marks.
+    /// Their text isn't read, so this is cheap enough to call on every click
+    pub fn commands(&self) -> Vec<CommandRecord> {
+        commands::command_records(&self.normal_grid)
+    }
+
+    /// Where the output is of the command whose prompt covers `line`
+    pub fn output_of_prompt(&self, line: usize) -> Option<Range<Point>> {
+        if self.active_screen == ActiveScreen::Alternate {
+            return None;
+        }
+
+        self.commands()
+            .into_iter()
+            .rfind(|record| {
+                let prompt_end = record.output.start.line.max(record.prompt.line + 1);
+                (record.prompt.line..prompt_end).contains(&line)
+            })
+            .map(|record| record.output)
+    }
+
+    /// Output of the most recent command, without the final line break
+    pub fn last_output(&self) -> Option<String> {
+        let record = self.commands().pop()?;
+        let text = self
+            .normal_grid
+            .text_in_range(record.output.start, record.output.end);
+
+        Some(text.trim_end_matches('\n').to_string())
+    }
+
+    /// Fold or unfold the output of the command that `line` belongs to,
+    /// counting its prompt. Returns `false` if there's nothing there to fold
+    pub fn toggle_fold(&mut self, line: usize) -> bool {
+        if self.active_screen == ActiveScreen::Alternate {
+            return false;
+        }
+
+        let block = commands::output_blocks(&self.normal_grid)
+            .into_iter()
+            .rfind(|(prompt, output)| (*prompt..output.end).contains(&line));
+
+        // A single line of output has nothing to hide
+        let Some((_, output)) = block.filter(|(_, output)| output.len() > 1) else {
+            return false;
+        };
+
+        let key = self.normal_grid.scrollback.dropped() + output.start;
+        if !self.folds.remove(&key) {
+            self.folds.insert(key);
+        }
+        self.is_dirty = true;
+        true
+    }
+
+    /// Lines hidden by folded output blocks. The line before each range,
+    /// the first of the output, stays in view to stand for the rest
+    pub fn folded_lines(&self) -> Vec<Range<usize>> {
+        if self.folds.is_empty() || self.active_screen == ActiveScreen::Alternate {
+            return Vec::new();
+        }
+
+        let dropped = self.normal_grid.scrollback.dropped();
+        commands::output_blocks(&self.normal_grid)
+            .into_iter()
+            .filter(|(_, output)| self.folds.contains(&(dropped + output.start)))
+            .map(|(_, output)| output.start + 1..output.end)
+            .collect()
+    }
+
+    /// The line shown on each row of the viewport. Folded lines are skipped,
+    /// keeping the bottom of the view in place, and later lines fill
+    /// any rows left empty at the top
+    pub fn display_lines(&self) -> Vec<usize> {
+        let grid = self.grid();
+        let scrollback_len = grid.scrollback_len();
+        let top = scrollback_len - self.scroll_offset.min(scrollback_len);
+        let bottom = (top + grid.rows).min(grid.total_lines());
+
+        let folded = self.folded_lines();
+        if folded.is_empty() {
+            return (top..bottom).collect();
+        }
+
+        let hidden = |line: usize| folded.iter().find(|range| range.contains(&line));
+
+        let mut lines = VecDeque::with_capacity(grid.rows);
+        let mut line = bottom;
+        while lines.len() < grid.rows && line > 0 {
+            match hidden(line - 1) {
+                Some(range) => line = range.start,
+                None => {
+                    line -= 1;
+                    lines.push_front(line);
+                }
+            }
+        }
+
+        let mut line = bottom;
+        while lines.len() < grid.rows && line < grid.total_lines() {
+            match hidden(line) {
+                Some(range) => line = range.end,
+                None => {
+                    lines.push_back(line);
+                    line += 1;
+                }
+            }
+        }
+
+        lines.into()
+    }
+
+    /// The position of viewport cell `(col, row)`. Rows past the
+    /// end of the view give the last line shown
+    pub fn point_at(&self, col: usize, row: usize) -> Point {
+        let lines = self.display_lines();
+        let line = lines.get(row).or(lines.last()).copied().unwrap_or(0);
+
+        Point { line, col }
+    }
+
     pub fn feed(&mut self, bytes: &[u8]) {
         if bytes.is_empty() {
             return;
@@ -569,50 +1428,151 @@ impl TerminalState {
             normal_grid: &mut self.normal_grid,
             alternate_grid: &mut self.alternate_grid,
             active_screen: &mut self.active_screen,
+            normal_saved_cursor: &mut self.normal_saved_cursor,
+            alternate_saved_cursor: &mut self.alternate_saved_cursor,
             attrs: &mut self.attrs,
+            palette: &mut self.palette,
+            charsets: &mut self.charsets,
             cursor_visible: &mut self.cursor_visible,
+            mouse: &mut self.mouse,
+            key_modes: &mut self.key_modes,
+            normal_kitty: &mut self.normal_kitty,
+            alternate_kitty: &mut self.alternate_kitty,
+            bracketed_paste: &mut self.bracketed_paste,
+            titles: &mut self.titles,
+            cwd: &mut self.cwd,
             current_link_id: &mut self.current_link_id,
             links: &mut self.links,
             next_link_id: &mut self.next_link_id,
+            replies: &mut self.replies,
+            clipboard_requests: &mut self.clipboard_requests,
+            notifications: &mut self.notifications,
+            command_started: &mut self.command_started,
+            focused: self.focused,
             config: self.config.clone(),
         };
 
         self.parser.advance(&mut performer, bytes);
     }
 
-    pub fn clear_dirty(&mut self) {
-        self.is_dirty = false;
-        self.grid_mut().clear_all_dirty_flags();
+    /// Encode a key event for the PTY with the active screen's keyboard modes
+    pub fn encode_key(&self, event: &KeyEvent, modifiers: ModifiersState) -> Option<Vec<u8>> {
+        let kitty = match self.active_screen {
+            ActiveScreen::Normal => &self.normal_kitty,
+            ActiveScreen::Alternate => &self.alternate_kitty,
+        };
+
+        self.key_modes.encode(event, modifiers, kitty.flags())
+    }
+
+    /// Prepare pasted text for the PTY. With bracketed paste on, the text is
+    /// wrapped in paste markers, and any markers inside it are removed so the
+    /// paste can't end early and have the rest run as typed input
+    pub fn format_paste(&self, text: &str) -> String {
+        if !self.bracketed_paste {
+            return text.to_string();
+        }
+
+        // Removing one marker could join the text around it into another
+        let mut payload = text.to_string();
+        while payload.contains("\x1b[200~") || payload.contains("\x1b[201~") {
+            payload = payload.replace("\x1b[200~", "").replace("\x1b[201~", "");
+        }
+
+        format!("\x1b[200~{payload}\x1b[201~")
+    }
+
+    /// The window title, from the configured template.
+    /// Falls back to the app name while the program hasn't set a title
+    pub fn window_title(&self) -> String {
+        let title = match self.titles.title.as_str() {
+            "" => "bnuuy",
+            title => title,
+        };
+        let shell = self.config.shell.first().map_or("", |program| {
+            program.rsplit(['/', '\\']).next().unwrap_or(program)
+        });
+        let cwd = self
+            .cwd
+            .as_deref()
+            .map(cwd::display_path)
+            .unwrap_or_default();
+        let grid = self.grid();
+
+        // One pass over the template, so placeholders that turn up inside
+        // substituted values are left alone
+        let mut out = String::new();
+        let mut rest = self.config.title_template.as_str();
+
+        while let Some(start) = rest.find('{') {
+            out.push_str(&rest[..start]);
+            rest = &rest[start..];
+
+            let Some(end) = rest.find('}') else {
+                break;
+            };
+
+            match &rest[1..end] {
+                "title" => out.push_str(title),
+                "icon_name" => out.push_str(&self.titles.icon_name),
+                "shell" => out.push_str(shell),
+                "cwd" => out.push_str(&cwd),
+                "cols" => out.push_str(&grid.cols.to_string()),
+                "rows" => out.push_str(&grid.rows.to_string()),
+                _ => {
+                    // Not a placeholder: keep the brace and look past it
+                    out.push('{');
+                    rest = &rest[1..];
+                    continue;
+                }
+            }
+
+            rest = &rest[end + 1..];
+        }
+
+        out.push_str(rest);
+        out
+    }
+
+    /// Take the replies generated by `feed` since the last call,
+    /// to be written to the PTY
+    pub fn take_replies(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.replies)
+    }
+
+    /// Take the OSC 52 requests made since the last call
+    pub fn take_clipboard_requests(&mut self) -> Vec<ClipboardRequest> {
+        std::mem::take(&mut self.clipboard_requests)
+    }
+
+    /// Take the notifications raised since the last call
+    pub fn take_notifications(&mut self) -> Vec<Notification> {
+        std::mem::take(&mut self.notifications)
+    }
+
+    /// The target to open for link `id`. Relative file paths are
+    /// resolved against the shell's working directory
+    pub fn link_target(&self, id: u32) -> Option<String> {
+        let url = self.links.get(&id)?;
+
+        let has_scheme = url.split_once(':').is_some_and(|(scheme, _)| {
+            scheme.len() > 1 && scheme.chars().all(char::is_alphanumeric)
+        });
+
+        match &self.cwd {
+            Some(cwd) if !has_scheme && std::path::Path::new(url).is_relative() => {
+                Some(cwd.join(url).display().to_string())
+            }
+            _ => Some(url.clone()),
+        }
     }
 
     pub fn get_link_at(&self, col: usize, row: usize) -> Option<u32> {
+        let line = *self.display_lines().get(row)?;
+
         self.grid()
-            .get_display_row(row, self.scroll_offset)
+            .loaded_row(line)
             .and_then(|r| r.cells.get(col))
             .and_then(|c| c.link_id)
     }
 }
-
-fn ansi_256_to_rgb(color_code: u8) -> Rgb {
-    match color_code {
-        // Standard 16 ANSI colors
-        0..=15 => {
-            let bright = color_code > 7;
-            let idx = if bright { color_code - 8 } else { color_code };
-            ansi_16(idx, bright)
-        }
-        // 6x6x6 color cube
-        16..=231 => {
-            let code = color_code - 16;
-            let r = (code / 36) * 51;
-            let g = ((code % 36) / 6) * 51;
-            let b = (code % 6) * 51;
-            Rgb(r, g, b)
-        }
-        // Grayscale ramp
-        232..=255 => {
-            let gray = (color_code - 232) * 10 + 8;
-            Rgb(gray, gray, gray)
-        }
-    }
-}
@@ -1,6 +1,18 @@
 use glyphon::Buffer;
+use std::borrow::Cow;
 use std::collections::VecDeque;
 use std::hash::{Hash, Hasher};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthChar;
+
+mod marks;
+mod packed;
+mod scrollback;
+mod search;
+
+pub use marks::PromptMark;
+pub use scrollback::Scrollback;
+pub use search::{Direction, Point, RegexSearch, SearchMatch};
 
 bitflags::bitflags! {
     /// Styles that affect a rendered cell
@@ -12,6 +24,26 @@ bitflags::bitflags! {
         const INVERSE = 0b0000_1000;
         const FAINT = 0b0001_0000;
         const UNDERCURL = 0b0010_0000;
+        /// First half of a double-width glyph
+        const WIDE_CHAR = 0b0100_0000;
+        /// Second half of a double-width glyph, holds no text of its own
+        const WIDE_CHAR_SPACER = 0b1000_0000;
+        /// Padding left in the last column when a wide glyph wrapped early
+        const LEADING_WIDE_CHAR_SPACER = 0b1_0000_0000;
+        /// Concealed text: the cell keeps its background but draws no glyph
+        const HIDDEN = 0b10_0000_0000;
+        const STRIKETHROUGH = 0b100_0000_0000;
+        const OVERLINE = 0b1000_0000_0000;
+        const DOUBLE_UNDERLINE = 0b1_0000_0000_0000;
+        const DOTTED_UNDERLINE = 0b10_0000_0000_0000;
+        const DASHED_UNDERLINE = 0b100_0000_0000_0000;
+
+        /// Every underline style. A cell has at most one of them
+        const ANY_UNDERLINE = Self::UNDERLINE.bits()
+            | Self::UNDERCURL.bits()
+            | Self::DOUBLE_UNDERLINE.bits()
+            | Self::DOTTED_UNDERLINE.bits()
+            | Self::DASHED_UNDERLINE.bits();
     }
 }
 
@@ -19,14 +51,72 @@ bitflags::bitflags! {
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Hash)]
 pub struct Rgb(pub u8, pub u8, pub u8);
 
+/// A cell color as the program set it. Indexed and default colors are
+/// looked up in the palette when the cell is drawn, so changing the palette
+/// recolors text that's already on screen or in history
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Hash)]
+pub enum Color {
+    /// The default foreground or background, depending on which one this is
+    #[default]
+    Default,
+    /// One of the 256 palette colors
+    Indexed(u8),
+    Rgb(Rgb),
+}
+
 /// One printable cell on the screen
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Cell {
+    /// First code point of the grapheme cluster shown in this cell
     pub ch: char,
-    pub fg: Rgb,
-    pub bg: Rgb,
+    pub fg: Color,
+    pub bg: Color,
     pub flags: CellFlags,
+    /// Color of underlines (SGR 58), or `None` to use `fg`
+    pub underline_color: Option<Color>,
     pub link_id: Option<u32>,
+    /// Rarely needed data, boxed so plain cells stay small
+    pub extra: Option<Box<CellExtra>>,
+}
+
+/// Cell data that most cells never need
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CellExtra {
+    /// Code points that extend `Cell::ch` into a full grapheme cluster:
+    /// combining marks, ZWJ sequences, variation selectors, modifiers
+    pub zerowidth: String,
+}
+
+impl Cell {
+    /// Append a code point to this cell's grapheme cluster
+    pub fn push_zerowidth(&mut self, c: char) {
+        self.extra
+            .get_or_insert_with(Default::default)
+            .zerowidth
+            .push(c);
+    }
+
+    /// Append the full grapheme cluster to `out`
+    pub fn push_grapheme(&self, out: &mut String) {
+        out.push(self.ch);
+        if let Some(extra) = &self.extra {
+            out.push_str(&extra.zerowidth);
+        }
+    }
+
+    /// Length of the full grapheme cluster in bytes
+    pub fn grapheme_len(&self) -> usize {
+        self.ch.len_utf8() + self.extra.as_ref().map_or(0, |e| e.zerowidth.len())
+    }
+
+    /// Whether `c` continues this cell's grapheme cluster rather than starting a new one
+    fn extends_grapheme(&self, c: char) -> bool {
+        let mut cluster = String::new();
+        self.push_grapheme(&mut cluster);
+        cluster.push(c);
+
+        cluster.graphemes(true).nth(1).is_none()
+    }
 }
 
 impl Hash for Cell {
@@ -34,6 +124,7 @@ impl Hash for Cell {
         self.fg.hash(state);
         self.bg.hash(state);
         self.flags.hash(state);
+        self.underline_color.hash(state);
         self.link_id.hash(state);
     }
 }
@@ -42,17 +133,23 @@ impl Default for Cell {
     fn default() -> Self {
         Self {
             ch: ' ',
-            fg: Rgb(0xC0, 0xC0, 0xC0),
-            bg: Rgb(0x00, 0x00, 0x00),
+            fg: Color::Default,
+            bg: Color::Default,
             flags: CellFlags::empty(),
+            underline_color: None,
             link_id: None,
+            extra: None,
         }
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct Row {
     pub cells: Vec<Cell>,
+    /// The line continues on the next row because of an auto-wrap
+    pub wrapped: bool,
+    /// Shell integration marks placed on this row, with the column of each
+    pub marks: Vec<(usize, PromptMark)>,
     pub is_dirty: bool,
     pub render_cache: Option<Buffer>,
 }
@@ -72,8 +169,39 @@ impl Row {
         self.is_dirty = true;
     }
 
+    /// Turn this row into a blank line of `cols` cells, keeping its allocations
+    fn reset(&mut self, cols: usize, blank: &Cell) {
+        self.cells.clear();
+        self.cells.resize(cols, blank.clone());
+        self.wrapped = false;
+        self.marks.clear();
+        self.is_dirty = true;
+    }
+
     pub fn text(&self) -> String {
-        self.cells.iter().map(|cell| cell.ch).collect()
+        let mut text = String::with_capacity(self.cells.len());
+        for cell in &self.cells {
+            if !cell.flags.contains(CellFlags::WIDE_CHAR_SPACER) {
+                cell.push_grapheme(&mut text);
+            }
+        }
+        text
+    }
+
+    /// If the cell at `x` is one half of a wide glyph, blank out the other half
+    /// so that overwriting `x` never leaves half a glyph behind
+    pub fn clear_wide_char_at(&mut self, x: usize, blank: &Cell) {
+        let Some(cell) = self.cells.get(x) else {
+            return;
+        };
+
+        if cell.flags.contains(CellFlags::WIDE_CHAR) {
+            if let Some(spacer) = self.cells.get_mut(x + 1) {
+                *spacer = blank.clone();
+            }
+        } else if cell.flags.contains(CellFlags::WIDE_CHAR_SPACER) && x > 0 {
+            self.cells[x - 1] = blank.clone();
+        }
     }
 }
 
@@ -86,115 +214,356 @@ pub struct ScreenGrid {
     /// older lines live above in `scrollback`
     pub lines: VecDeque<Row>,
 
+    /// History that scrolled off the top of the screen, oldest first
+    pub scrollback: Scrollback,
+
     /// Cursor position in the visible area
     pub cur_x: usize,
     pub cur_y: usize,
 
-    /// Max scrollback lines kept
-    scrollback_capacity: usize,
-
     pub full_redraw_needed: bool,
     pub scroll_top: usize,
     pub scroll_bottom: usize,
 
-    default_fg: Rgb,
-    default_bg: Rgb,
     deferred_wrap: bool,
+
+    /// One entry per column, `true` where a tab stop is set
+    tab_stops: Vec<bool>,
 }
 
 impl ScreenGrid {
-    pub fn new(
-        cols: usize,
-        rows: usize,
-        scrollback: usize,
-        default_fg: Rgb,
-        default_bg: Rgb,
-    ) -> Self {
-        let mut grid = ScreenGrid {
+    pub fn new(cols: usize, rows: usize, scrollback: Scrollback) -> Self {
+        ScreenGrid {
             rows,
             cols,
             scroll_top: 0,
             scroll_bottom: rows - 1,
             cur_x: 0,
             cur_y: 0,
-            lines: VecDeque::with_capacity(rows + scrollback),
-            scrollback_capacity: scrollback,
+            lines: (0..rows).map(|_| blank_row(cols)).collect(),
+            scrollback,
             full_redraw_needed: true,
-            default_fg,
-            default_bg,
             deferred_wrap: false,
-        };
+            tab_stops: default_tab_stops(cols),
+        }
+    }
 
-        grid.resize(cols, rows);
-        grid
+    /// Mark every row on screen and in the history view for redrawing,
+    /// such as after the palette the colors resolve against changed
+    pub fn mark_all_dirty(&mut self) {
+        for row in self.lines.iter_mut().chain(self.scrollback.view_mut()) {
+            row.is_dirty = true;
+        }
+        self.full_redraw_needed = true;
     }
 
     pub fn clear_all_dirty_flags(&mut self) {
         self.full_redraw_needed = false;
-        for row in self.lines.iter_mut() {
+        for row in self.lines.iter_mut().chain(self.scrollback.view_mut()) {
             row.is_dirty = false;
         }
     }
 
-    /// Write one glyph together with its colours + flags
+    /// Write one glyph together with its colours + flags.
+    /// Wide glyphs take two columns: the glyph itself followed by a spacer cell
     pub fn put_char_ex(
         &mut self,
         ch: char,
-        fg: Rgb,
-        bg: Rgb,
+        fg: Color,
+        bg: Color,
         flags: CellFlags,
+        underline_color: Option<Color>,
         link_id: Option<u32>,
     ) {
+        let width = ch.width().unwrap_or(1);
+
+        // Zero-width code points and anything that continues a grapheme
+        // cluster (ZWJ sequences, modifiers, regional indicator pairs)
+        // belong to the previous cell rather than taking a column
+        if !ch.is_ascii() {
+            if let Some(x) = self.previous_cell_x() {
+                let y = self.cur_y;
+                if let Some(row) = self.visible_row_mut(y)
+                    && (width == 0 || row.cells[x].extends_grapheme(ch))
+                {
+                    row.cells[x].push_zerowidth(ch);
+                    row.mark_dirty();
+                    return;
+                }
+            } else if width == 0 {
+                return;
+            }
+        }
+
+        let width = width.clamp(1, 2).min(self.cols);
+
         if self.deferred_wrap {
-            self.line_feed();
-            self.cur_x = 0;
-            self.deferred_wrap = false;
+            self.wrap_line();
+        }
+
+        let blank_cell = Cell::default();
+
+        // A wide glyph that doesn't fit in the last column wraps early,
+        // leaving a spacer behind so reflow can stitch the line back together
+        if width == 2 && self.cur_x + 1 >= self.cols {
+            let x = self.cur_x;
+            if let Some(row) = self.visible_row_mut(self.cur_y) {
+                row.clear_wide_char_at(x, &blank_cell);
+                row.cells[x] = Cell {
+                    flags: CellFlags::LEADING_WIDE_CHAR_SPACER,
+                    ..blank_cell.clone()
+                };
+            }
+            self.wrap_line();
         }
 
         let x = self.cur_x;
         let y = self.cur_y;
+        let cols = self.cols;
 
-        if x < self.cols {
-            if let Some(row) = self.visible_row_mut(y) {
-                row.cells[x] = Cell {
-                    ch,
+        if x < cols
+            && let Some(row) = self.visible_row_mut(y)
+        {
+            row.clear_wide_char_at(x, &blank_cell);
+
+            let mut cell_flags = flags;
+            if width == 2 {
+                row.clear_wide_char_at(x + 1, &blank_cell);
+                row.cells[x + 1] = Cell {
+                    ch: ' ',
                     fg,
                     bg,
-                    flags,
+                    flags: flags | CellFlags::WIDE_CHAR_SPACER,
+                    underline_color,
                     link_id,
+                    extra: None,
                 };
-                row.mark_dirty();
+                cell_flags |= CellFlags::WIDE_CHAR;
             }
+
+            row.cells[x] = Cell {
+                ch,
+                fg,
+                bg,
+                flags: cell_flags,
+                underline_color,
+                link_id,
+                extra: None,
+            };
+
+            // Rewriting the end of the row: whether it wraps is decided afresh
+            // by the next character
+            if x + width >= cols {
+                row.wrapped = false;
+            }
+            row.mark_dirty();
         }
 
+        if width == 2 {
+            self.cur_x += 1;
+        }
         self.advance_cursor();
     }
 
-    /// Clear everything and allocate blank rows
+    /// Column of the cell written just before the cursor, if any
+    fn previous_cell_x(&self) -> Option<usize> {
+        let x = if self.deferred_wrap {
+            self.cur_x
+        } else {
+            self.cur_x.checked_sub(1)?
+        };
+
+        let row = self.visible_row(self.cur_y)?;
+        if row
+            .cells
+            .get(x)?
+            .flags
+            .contains(CellFlags::WIDE_CHAR_SPACER)
+        {
+            x.checked_sub(1)
+        } else {
+            Some(x)
+        }
+    }
+
+    /// Continue on the next line after an auto-wrap
+    fn wrap_line(&mut self) {
+        self.deferred_wrap = false;
+        if let Some(row) = self.visible_row_mut(self.cur_y) {
+            row.wrapped = true;
+        }
+        self.line_feed();
+        self.cur_x = 0;
+    }
+
+    /// Resize the grid, re-wrapping soft-wrapped lines to the new width.
+    /// Scrollback is kept and the cursor stays on the same logical character.
+    ///
+    /// A new width re-wraps all of the history held in memory. Rows already
+    /// moved to disk keep their old width, and are padded or cut off to fit
+    /// when shown
     pub fn resize(&mut self, cols: usize, rows: usize) {
-        if self.cols == cols && self.rows == rows {
+        if cols == 0 || rows == 0 || (self.cols == cols && self.rows == rows) {
             return;
         }
 
-        self.cols = cols;
-        self.rows = rows;
+        if self.cols == cols {
+            self.resize_rows(rows);
+            return;
+        }
+
+        let blank_cell = Cell::default();
+
+        // A pending wrap means the cursor sits just past the last column
+        let cursor_offset = self.cur_x + usize::from(self.deferred_wrap);
+
+        // Blank rows below the cursor are dropped so the content stays
+        // anchored to the cursor rather than to the bottom of the screen
+        let last_used = self
+            .lines
+            .iter()
+            .rposition(|row| row.wrapped || row.cells.iter().any(|c| *c != blank_cell))
+            .unwrap_or(0)
+            .max(self.cur_y);
+        self.lines.truncate(last_used + 1);
+
+        // History is unpacked and re-wrapped a line at a time, and rows that
+        // can no longer reach the screen go straight back into scrollback
+        let history = self.scrollback.drain();
+        let cursor_line = history.len() + self.cur_y;
+        let screen = std::mem::take(&mut self.lines);
+
+        let mut new_lines = VecDeque::new();
+        let mut flushed = 0;
+        let mut cursor = None; // (line index, col, deferred wrap)
+
+        // Join soft-wrapped rows back into logical lines
+        let mut current: Vec<Cell> = Vec::new();
+        let mut current_marks: Vec<(usize, PromptMark)> = Vec::new();
+        let mut current_cursor = None;
+
+        for (idx, mut row) in history.chain(screen).enumerate() {
+            if idx == cursor_line {
+                current_cursor = Some(current.len() + cursor_offset);
+            }
+
+            if row.wrapped
+                && row
+                    .cells
+                    .last()
+                    .is_some_and(|c| c.flags.contains(CellFlags::LEADING_WIDE_CHAR_SPACER))
+            {
+                row.cells.pop();
+            }
+
+            current_marks.extend(row.marks.iter().map(|&(x, mark)| (current.len() + x, mark)));
+            current.extend(row.cells);
+            if row.wrapped {
+                continue;
+            }
+
+            let line = std::mem::take(&mut current);
+            if let Some((y, x, deferred)) = rewrap_line(
+                line,
+                std::mem::take(&mut current_marks),
+                cols,
+                &blank_cell,
+                current_cursor.take(),
+                &mut new_lines,
+            ) {
+                cursor = Some((flushed + y, x, deferred));
+            }
 
-        let fg = self.default_fg;
-        let bg = self.default_bg;
+            // Everything above the cursor may end up in scrollback; only the
+            // last `rows` re-wrapped rows can still be pulled onto the screen
+            if cursor.is_none() {
+                while new_lines.len() > rows
+                    && let Some(row) = new_lines.pop_front()
+                {
+                    self.scrollback.push(row);
+                    flushed += 1;
+                }
+            }
+        }
+        if !current.is_empty()
+            && let Some((y, x, deferred)) = rewrap_line(
+                current,
+                current_marks,
+                cols,
+                &blank_cell,
+                current_cursor,
+                &mut new_lines,
+            )
+        {
+            cursor = Some((flushed + y, x, deferred));
+        }
 
-        self.lines.clear();
-        for _ in 0..rows {
-            self.lines.push_back(blank_row(cols, fg, bg));
+        let (cursor_y, cursor_x, deferred_wrap) = cursor.unwrap_or((flushed, 0, false));
+        let cursor_y = cursor_y - flushed;
+
+        // Keep as much as possible at the bottom of the screen, but never
+        // push the cursor above the viewport
+        let top = new_lines.len().saturating_sub(rows).min(cursor_y);
+        new_lines.truncate(top + rows);
+        while new_lines.len() < top + rows {
+            new_lines.push_back(blank_row(cols));
         }
 
-        self.cur_x = 0;
-        self.cur_y = 0;
+        self.lines = new_lines.split_off(top);
+        for row in new_lines {
+            self.scrollback.push(row);
+        }
+
+        self.cols = cols;
+        self.rows = rows;
+        self.cur_x = cursor_x;
+        self.cur_y = cursor_y - top;
         self.scroll_top = 0;
         self.scroll_bottom = rows - 1;
-        self.deferred_wrap = false;
+        self.deferred_wrap = deferred_wrap;
+        self.tab_stops = default_tab_stops(cols);
         self.full_redraw_needed = true;
     }
 
+    /// Change the height only, which needs no re-wrapping. Rows move between
+    /// the screen and history the same way as in `resize`
+    fn resize_rows(&mut self, rows: usize) {
+        let blank_cell = Cell::default();
+
+        let last_used = self
+            .lines
+            .iter()
+            .rposition(|row| row.wrapped || row.cells.iter().any(|c| *c != blank_cell))
+            .unwrap_or(0)
+            .max(self.cur_y);
+        self.lines.truncate(last_used + 1);
+
+        // Rows above the cursor that no longer fit go into history
+        let excess = self.lines.len().saturating_sub(rows).min(self.cur_y);
+        for row in self.lines.drain(..excess).collect::<Vec<_>>() {
+            self.scrollback.push(row);
+        }
+        self.cur_y -= excess;
+        self.lines.truncate(rows);
+
+        // and a taller screen pulls history back down
+        while self.lines.len() < rows
+            && let Some(mut row) = self.scrollback.pop()
+        {
+            row.cells.resize(self.cols, blank_cell.clone());
+            self.lines.push_front(row);
+            self.cur_y += 1;
+        }
+        while self.lines.len() < rows {
+            self.lines.push_back(blank_row(self.cols));
+        }
+
+        self.rows = rows;
+        self.scroll_top = 0;
+        self.scroll_bottom = rows - 1;
+        self.mark_all_dirty();
+    }
+
     /// Move cursor to a given position
     pub fn set_cursor_pos(&mut self, x: usize, y: usize) {
         if let Some(row) = self.visible_row_mut(self.cur_y) {
@@ -215,11 +584,9 @@ impl ScreenGrid {
     pub fn clear_line(&mut self) {
         self.deferred_wrap = false;
         let cols = self.cols;
-        let fg = self.default_fg;
-        let bg = self.default_bg;
 
         if let Some(row) = self.visible_row_mut(self.cur_y) {
-            *row = blank_row(cols, fg, bg);
+            *row = blank_row(cols);
         }
     }
 
@@ -229,13 +596,10 @@ impl ScreenGrid {
         let cur_x = self.cur_x;
         let cur_y = self.cur_y;
         let cols = self.cols;
-        let blank_cell = Cell {
-            fg: self.default_fg,
-            bg: self.default_bg,
-            ..Default::default()
-        };
+        let blank_cell = Cell::default();
 
         if let Some(row) = self.visible_row_mut(cur_y) {
+            row.clear_wide_char_at(cur_x, &blank_cell);
             for x in 0..=cur_x {
                 if x < cols {
                     row.cells[x] = blank_cell.clone();
@@ -251,16 +615,14 @@ impl ScreenGrid {
         let cur_x = self.cur_x;
         let cols = self.cols;
 
-        let blank_cell = Cell {
-            fg: self.default_fg,
-            bg: self.default_bg,
-            ..Default::default()
-        };
+        let blank_cell = Cell::default();
 
         if let Some(row) = self.visible_row_mut(self.cur_y) {
+            row.clear_wide_char_at(cur_x, &blank_cell);
             for x in cur_x..cols {
                 row.cells[x] = blank_cell.clone();
             }
+            row.wrapped = false;
             row.mark_dirty();
         }
     }
@@ -271,12 +633,10 @@ impl ScreenGrid {
         let cur_y = self.cur_y;
         let scroll_top = self.scroll_top;
         let cols = self.cols;
-        let fg = self.default_fg;
-        let bg = self.default_bg;
 
         for y in scroll_top..cur_y {
             if let Some(row) = self.visible_row_mut(y) {
-                *row = blank_row(cols, fg, bg);
+                *row = blank_row(cols);
             }
         }
 
@@ -292,12 +652,9 @@ impl ScreenGrid {
         let scroll_bottom = self.scroll_bottom;
         let cols = self.cols;
 
-        let fg = self.default_fg;
-        let bg = self.default_bg;
-
         for y in (cur_y + 1)..=scroll_bottom {
             if let Some(row) = self.visible_row_mut(y) {
-                *row = blank_row(cols, fg, bg);
+                *row = blank_row(cols);
                 row.is_dirty = true;
             }
         }
@@ -309,12 +666,9 @@ impl ScreenGrid {
         let scroll_bottom = self.scroll_bottom;
         let cols = self.cols;
 
-        let fg = self.default_fg;
-        let bg = self.default_bg;
-
         for y in scroll_top..=scroll_bottom {
             if let Some(row) = self.visible_row_mut(y) {
-                *row = blank_row(cols, fg, bg);
+                *row = blank_row(cols);
             }
         }
 
@@ -323,6 +677,33 @@ impl ScreenGrid {
         self.full_redraw_needed = true;
     }
 
+    /// Fill the screen with `E`s for the alignment test (DECALN),
+    /// resetting the scroll region and moving the cursor home
+    pub fn fill_alignment_pattern(&mut self) {
+        let cell = Cell {
+            ch: 'E',
+            ..Default::default()
+        };
+
+        for row in self.lines.iter_mut() {
+            row.reset(self.cols, &cell);
+        }
+
+        self.scroll_top = 0;
+        self.scroll_bottom = self.rows - 1;
+        self.set_cursor_pos(0, 0);
+        self.full_redraw_needed = true;
+    }
+
+    /// Return to the initial state (RIS): blank screen, cursor home,
+    /// full-screen scroll region and default tab stops. History is kept
+    pub fn reset(&mut self) {
+        self.scroll_top = 0;
+        self.scroll_bottom = self.rows - 1;
+        self.tab_stops = default_tab_stops(self.cols);
+        self.clear_all();
+    }
+
     /// Inserts `n` blank lines at the cursor's current row
     /// Lines at and below the cursor are pushed down, within the scroll region
     pub fn insert_lines(&mut self, n: usize) {
@@ -338,11 +719,8 @@ impl ScreenGrid {
             return;
         }
 
-        let fg = self.default_fg;
-        let bg = self.default_bg;
-
-        let region_start_idx = self.scrollback_len() + y;
-        let region_end_idx = self.scrollback_len() + self.scroll_bottom;
+        let region_start_idx = y;
+        let region_end_idx = self.scroll_bottom;
 
         let lines_slice = self.lines.make_contiguous();
         if region_end_idx >= lines_slice.len() {
@@ -353,13 +731,22 @@ impl ScreenGrid {
 
         affected_region.rotate_right(n);
 
-        for i in 0..n {
-            affected_region[i] = blank_row(self.cols, fg, bg);
+        for row in affected_region.iter_mut().take(n) {
+            *row = blank_row(self.cols);
+        }
+
+        // The continuation of the last row was pushed out of the region
+        if let Some(row) = affected_region.last_mut() {
+            row.wrapped = false;
         }
 
         for row in affected_region.iter_mut() {
             row.is_dirty = true;
         }
+
+        if let Some(above) = y.checked_sub(1) {
+            self.unwrap_row(above);
+        }
     }
 
     /// Deletes `n` lines at the cursor's current row
@@ -377,11 +764,8 @@ impl ScreenGrid {
             return;
         }
 
-        let fg = self.default_fg;
-        let bg = self.default_bg;
-
-        let region_start_idx = self.scrollback_len() + y;
-        let region_end_idx = self.scrollback_len() + self.scroll_bottom;
+        let region_start_idx = y;
+        let region_end_idx = self.scroll_bottom;
 
         let lines_slice = self.lines.make_contiguous();
         if region_end_idx >= lines_slice.len() {
@@ -394,12 +778,24 @@ impl ScreenGrid {
 
         let affected_len = affected_region.len();
         for i in 0..n {
-            affected_region[affected_len - 1 - i] = blank_row(self.cols, fg, bg);
+            affected_region[affected_len - 1 - i] = blank_row(self.cols);
+        }
+
+        // Rows pulled up from the bottom are now followed by blank lines
+        if let Some(row) = (affected_len - n)
+            .checked_sub(1)
+            .and_then(|i| affected_region.get_mut(i))
+        {
+            row.wrapped = false;
         }
 
         for row in affected_region.iter_mut() {
             row.is_dirty = true;
         }
+
+        if let Some(above) = y.checked_sub(1) {
+            self.unwrap_row(above);
+        }
     }
 
     /// Inserts `n` blank characters at the cursor position
@@ -409,19 +805,32 @@ impl ScreenGrid {
         let x = self.cur_x;
         let cols = self.cols;
 
-        let blank_cell = Cell {
-            fg: self.default_fg,
-            bg: self.default_bg,
-            ..Default::default()
-        };
+        let blank_cell = Cell::default();
 
         if let Some(row) = self.visible_row_mut(y) {
+            if row
+                .cells
+                .get(x)
+                .is_some_and(|c| c.flags.contains(CellFlags::WIDE_CHAR_SPACER))
+            {
+                row.clear_wide_char_at(x, &blank_cell);
+                row.cells[x] = blank_cell.clone();
+            }
+
             for _ in 0..n {
                 if x < cols {
                     row.cells.insert(x, blank_cell.clone());
                     row.cells.truncate(cols);
                 }
             }
+
+            // Don't leave a wide glyph whose spacer was pushed off the edge
+            if let Some(last) = row.cells.last_mut()
+                && last.flags.contains(CellFlags::WIDE_CHAR)
+            {
+                *last = blank_cell.clone();
+            }
+            row.wrapped = false;
             row.mark_dirty();
         }
     }
@@ -433,13 +842,12 @@ impl ScreenGrid {
         let x = self.cur_x;
         let cols = self.cols;
 
-        let blank_cell = Cell {
-            fg: self.default_fg,
-            bg: self.default_bg,
-            ..Default::default()
-        };
+        let blank_cell = Cell::default();
 
         if let Some(row) = self.visible_row_mut(y) {
+            row.clear_wide_char_at(x, &blank_cell);
+            row.clear_wide_char_at((x + n).min(cols).saturating_sub(1), &blank_cell);
+
             for _ in 0..n {
                 if x < row.cells.len() {
                     row.cells.remove(x);
@@ -449,25 +857,49 @@ impl ScreenGrid {
             while row.cells.len() < cols {
                 row.cells.push(blank_cell.clone());
             }
+            row.wrapped = false;
             row.mark_dirty();
         }
     }
 
-    /// Handle \n (line feed)
+    /// Handle \n (line feed).
+    /// An explicit newline cancels any pending auto-wrap, so the row ends in a hard break
     pub fn line_feed(&mut self) {
-        if self.deferred_wrap {
-            self.deferred_wrap = false;
-            return;
-        }
+        self.deferred_wrap = false;
 
         if self.cur_y == self.scroll_bottom {
             self.scroll_up(1);
-        } else {
+        } else if self.cur_y + 1 < self.rows {
             self.cur_y += 1;
         }
     }
 
-    /// Scroll the viewport up by `n` lines
+    /// Move the cursor up one line (RI), scrolling the scroll region down
+    /// when the cursor is at its top
+    pub fn reverse_index(&mut self) {
+        self.deferred_wrap = false;
+
+        if self.cur_y == self.scroll_top {
+            self.scroll_down(1);
+        } else if self.cur_y > 0 {
+            self.cur_y -= 1;
+        }
+    }
+
+    /// Handle \r (carriage return)
+    pub fn carriage_return(&mut self) {
+        self.deferred_wrap = false;
+        self.cur_x = 0;
+    }
+
+    /// Handle \x08 (backspace)
+    pub fn backspace(&mut self) {
+        self.deferred_wrap = false;
+        self.cur_x = self.cur_x.saturating_sub(1);
+    }
+
+    /// Scroll the scroll region up by `n` lines.
+    /// Lines leaving the top of the screen are kept in scrollback
     pub fn scroll_up(&mut self, n: usize) {
         let scrollable_lines_in_region = self.scroll_bottom.saturating_sub(self.scroll_top) + 1;
         let n = n.min(scrollable_lines_in_region);
@@ -476,28 +908,132 @@ impl ScreenGrid {
             return;
         }
 
-        let fg = self.default_fg;
-        let bg = self.default_bg;
+        let blank_cell = Cell::default();
 
-        let top_idx = self.scrollback_len() + self.scroll_top;
+        for _ in 0..n {
+            let Some(row) = self.lines.remove(self.scroll_top) else {
+                break;
+            };
+
+            // Only lines leaving the top of the screen become history.
+            // Either way the row is recycled as the new blank line
+            let mut row = if self.scroll_top == 0 {
+                self.scrollback.push(row)
+            } else {
+                row
+            };
+            row.reset(self.cols, &blank_cell);
+
+            let bottom_idx = self.scroll_bottom.min(self.lines.len());
+            self.lines.insert(bottom_idx, row);
+        }
 
-        let scrolled_off_rows: Vec<Row> = if self.lines.len() >= top_idx + n {
-            self.lines.drain(top_idx..top_idx + n).collect()
-        } else {
-            vec![]
-        };
+        // Rows that moved up are now followed by blank lines, and the row
+        // above a partial scroll region no longer continues into it
+        if let Some(y) = self.scroll_bottom.checked_sub(n)
+            && y >= self.scroll_top
+        {
+            self.unwrap_row(y);
+        }
+        if let Some(above) = self.scroll_top.checked_sub(1) {
+            self.unwrap_row(above);
+        }
+    }
+
+    /// Scroll the scroll region down by `n` lines, inserting blank lines
+    /// at its top. Lines pushed past the bottom of the region are discarded
+    pub fn scroll_down(&mut self, n: usize) {
+        let scrollable_lines_in_region = self.scroll_bottom.saturating_sub(self.scroll_top) + 1;
+        let n = n.min(scrollable_lines_in_region);
+
+        if n == 0 {
+            return;
+        }
+
+        let blank_cell = Cell::default();
 
         for _ in 0..n {
-            let bottom_idx = self.scrollback_len() + self.scroll_bottom + 1;
-            let clamped_idx = bottom_idx.min(self.lines.len());
-            self.lines.insert(clamped_idx, blank_row(self.cols, fg, bg));
+            let Some(mut row) = self.lines.remove(self.scroll_bottom) else {
+                break;
+            };
+
+            row.reset(self.cols, &blank_cell);
+            self.lines.insert(self.scroll_top, row);
+        }
+
+        // The last row's continuation was pushed out of the region, and the
+        // row above the region is now followed by a blank line
+        self.unwrap_row(self.scroll_bottom);
+        if let Some(above) = self.scroll_top.checked_sub(1) {
+            self.unwrap_row(above);
         }
+    }
 
-        for row in scrolled_off_rows {
-            if self.scrollback_capacity > 0 {
-                self.push_scrollback(row);
+    /// Mark a visible row as ending in a hard line break
+    fn unwrap_row(&mut self, y: usize) {
+        if let Some(row) = self.visible_row_mut(y) {
+            row.wrapped = false;
+        }
+    }
+
+    /// Move the cursor forward `n` tab stops (HT / CHT),
+    /// stopping at the last column if there are no more stops
+    pub fn tab_forward(&mut self, n: usize) {
+        self.deferred_wrap = false;
+        let last_col = self.cols.saturating_sub(1);
+
+        for _ in 0..n {
+            match (self.cur_x + 1..self.cols).find(|&x| self.tab_stops[x]) {
+                Some(x) => self.cur_x = x,
+                None => {
+                    self.cur_x = last_col;
+                    break;
+                }
+            }
+        }
+
+        if let Some(row) = self.visible_row_mut(self.cur_y) {
+            row.mark_dirty();
+        }
+    }
+
+    /// Move the cursor back `n` tab stops (CBT),
+    /// stopping at the first column if there are no more stops
+    pub fn tab_backward(&mut self, n: usize) {
+        self.deferred_wrap = false;
+
+        for _ in 0..n {
+            match (0..self.cur_x).rev().find(|&x| self.tab_stops[x]) {
+                Some(x) => self.cur_x = x,
+                None => {
+                    self.cur_x = 0;
+                    break;
+                }
             }
         }
+
+        if let Some(row) = self.visible_row_mut(self.cur_y) {
+            row.mark_dirty();
+        }
+    }
+
+    /// Set a tab stop at the cursor column (HTS)
+    pub fn set_tab_stop(&mut self) {
+        if let Some(stop) = self.tab_stops.get_mut(self.cur_x) {
+            *stop = true;
+        }
+    }
+
+    /// Clear the tab stop at the cursor column (TBC 0)
+    pub fn clear_tab_stop(&mut self) {
+        if let Some(stop) = self.tab_stops.get_mut(self.cur_x) {
+            *stop = false;
+        }
+    }
+
+    /// Clear every tab stop (TBC 3)
+    pub fn clear_all_tab_stops(&mut self) {
+        self.tab_stops.fill(false);
     }
 
     fn advance_cursor(&mut self) {
@@ -509,45 +1045,217 @@ impl ScreenGrid {
     }
 
     pub fn visible_row(&self, y: usize) -> Option<&Row> {
-        let sb = self.scrollback_len();
-        self.lines.get(sb + y)
+        self.lines.get(y)
     }
 
     pub fn visible_row_mut(&mut self, y: usize) -> Option<&mut Row> {
-        self.lines.get_mut(self.scrollback_len() + y)
+        self.lines.get_mut(y)
     }
 
     pub fn scrollback_len(&self) -> usize {
-        self.lines.len().saturating_sub(self.rows)
+        self.scrollback.len()
+    }
+
+    /// Text between two positions, from `start` up to (but not including)
+    /// `end`. Soft-wrapped rows are joined into one logical line
+    pub fn text_in_range(&self, start: Point, end: Point) -> String {
+        let mut result = String::new();
+        let mut prev_wrapped = false;
+
+        for line in start.line..=end.line {
+            let Some(row) = self.row_at_line(line) else {
+                break;
+            };
+
+            if line > start.line && !prev_wrapped {
+                result.push('\n');
+            }
+
+            let line_start = if line == start.line { start.col } else { 0 };
+            let line_end = if line == end.line { end.col } else { self.cols };
+
+            let mut line_text = String::new();
+            for cell in row
+                .cells
+                .iter()
+                .skip(line_start)
+                .take(line_end.saturating_sub(line_start))
+            {
+                if !cell
+                    .flags
+                    .intersects(CellFlags::WIDE_CHAR_SPACER | CellFlags::LEADING_WIDE_CHAR_SPACER)
+                {
+                    cell.push_grapheme(&mut line_text);
+                }
+            }
+
+            // Trailing blanks before a hard line break are padding, not content
+            if line < end.line && !row.wrapped {
+                result.push_str(line_text.trim_end());
+            } else {
+                result.push_str(&line_text);
+            }
+
+            prev_wrapped = row.wrapped;
+        }
+
+        result
+    }
+
+    /// Unpack the history rows among `lines`, given in ascending order,
+    /// so `loaded_row` can return them
+    pub fn load_lines(&mut self, lines: &[usize]) {
+        let blank_cell = Cell::default();
+
+        self.scrollback
+            .set_view(lines.iter().copied(), self.cols, &blank_cell);
+    }
+
+    /// Row at `line` if it's on screen or loaded with `load_lines`
+    pub fn loaded_row(&self, line: usize) -> Option<&Row> {
+        let scrollback_len = self.scrollback.len();
+
+        if line < scrollback_len {
+            self.scrollback.get(line)
+        } else {
+            self.lines.get(line - scrollback_len)
+        }
     }
 
+    /// Row `y` of the viewport when scrolled `offset` lines back into history.
+    /// History rows are only available once loaded with `load_lines`
     pub fn get_display_row(&self, y: usize, offset: usize) -> Option<&Row> {
-        let total_lines = self.lines.len();
-        let top_visible_idx = total_lines.saturating_sub(self.rows);
-        let requested_idx = top_visible_idx.saturating_sub(offset);
+        let scrollback_len = self.scrollback.len();
+        self.loaded_row(scrollback_len - offset.min(scrollback_len) + y)
+    }
 
-        self.lines.get(requested_idx + y)
+    /// Number of lines in history and on screen together
+    pub fn total_lines(&self) -> usize {
+        self.scrollback.len() + self.lines.len()
     }
 
-    fn push_scrollback(&mut self, row: Row) {
-        self.lines.push_front(row);
+    /// Row at `line`, counted from the oldest history line,
+    /// so visible row `y` is line `scrollback_len() + y`
+    pub fn row_at_line(&self, line: usize) -> Option<Cow<'_, Row>> {
+        let scrollback_len = self.scrollback.len();
 
-        while self.lines.len() > self.rows + self.scrollback_capacity {
-            self.lines.pop_front();
+        if line >= scrollback_len {
+            self.lines.get(line - scrollback_len).map(Cow::Borrowed)
+        } else if let Some(row) = self.scrollback.get(line) {
+            Some(Cow::Borrowed(row))
+        } else {
+            self.scrollback.unpack(line).map(Cow::Owned)
         }
     }
+
+    /// Whether the row at `line`, counted like in `row_at_line`, is soft-wrapped
+    /// into the next. Rows in history aren't unpacked to find out
+    pub fn is_wrapped(&self, line: usize) -> bool {
+        let scrollback_len = self.scrollback.len();
+
+        if line >= scrollback_len {
+            self.lines
+                .get(line - scrollback_len)
+                .is_some_and(|row| row.wrapped)
+        } else {
+            self.scrollback.is_wrapped(line).unwrap_or(false)
+        }
+    }
+}
+
+fn default_tab_stops(cols: usize) -> Vec<bool> {
+    (0..cols).map(|x| x > 0 && x % 8 == 0).collect()
+}
+
+/// Wrap one logical line into rows of `cols` cells, appending them to `out`.
+/// If `cursor_offset` points into the line, returns where the cursor ended up
+/// as (index into `out`, col, whether a wrap is pending)
+fn rewrap_line(
+    mut cells: Vec<Cell>,
+    mut marks: Vec<(usize, PromptMark)>,
+    cols: usize,
+    blank_cell: &Cell,
+    cursor_offset: Option<usize>,
+    out: &mut VecDeque<Row>,
+) -> Option<(usize, usize, bool)> {
+    while cells.last() == Some(blank_cell) {
+        cells.pop();
+    }
+
+    // Keep the cells that marks and the cursor sit on, even if they're blank
+    let marked_len = marks.iter().map(|&(offset, _)| offset + 1).max();
+    if let Some(offset) = cursor_offset.max(marked_len)
+        && offset > cells.len()
+    {
+        cells.resize(offset, blank_cell.clone());
+    }
+
+    marks.sort_by_key(|&(offset, _)| offset);
+    let mut marks = marks.into_iter().peekable();
+    let mut row_marks = Vec::new();
+
+    let len = cells.len();
+    let mut cursor = None;
+    let mut row_cells: Vec<Cell> = Vec::with_capacity(cols);
+
+    for (idx, cell) in cells.into_iter().enumerate() {
+        let wide_at_edge =
+            cell.flags.contains(CellFlags::WIDE_CHAR) && cols > 1 && row_cells.len() == cols - 1;
+
+        if row_cells.len() == cols || wide_at_edge {
+            if wide_at_edge {
+                row_cells.push(Cell {
+                    flags: CellFlags::LEADING_WIDE_CHAR_SPACER,
+                    ..blank_cell.clone()
+                });
+            }
+
+            out.push_back(Row {
+                cells: std::mem::replace(&mut row_cells, Vec::with_capacity(cols)),
+                wrapped: true,
+                marks: std::mem::take(&mut row_marks),
+                is_dirty: true,
+                render_cache: None,
+            });
+        }
+
+        if cursor_offset == Some(idx) {
+            cursor = Some((out.len(), row_cells.len(), false));
+        }
+        while let Some((_, mark)) = marks.next_if(|&(offset, _)| offset == idx) {
+            row_marks.push((row_cells.len(), mark));
+        }
+        row_cells.push(cell);
+    }
+
+    if cursor_offset == Some(len) {
+        cursor = if row_cells.len() == cols {
+            // Cursor lands exactly on a row boundary: keep the wrap pending
+            Some((out.len(), cols - 1, true))
+        } else {
+            Some((out.len(), row_cells.len(), false))
+        };
+    }
+
+    row_cells.resize(cols, blank_cell.clone());
+    out.push_back(Row {
+        cells: row_cells,
+        wrapped: false,
+        marks: row_marks,
+        is_dirty: true,
+        render_cache: None,
+    });
+
+    cursor
 }
 
-fn blank_row(cols: usize, default_fg: Rgb, default_bg: Rgb) -> Row {
-    let blank_cell = Cell {
-        fg: default_fg,
-        bg: default_bg,
-        ..Default::default()
-    };
-    let cells = std::iter::repeat(blank_cell).take(cols).collect();
+fn blank_row(cols: usize) -> Row {
+    let cells = std::iter::repeat_n(Cell::default(), cols).collect();
 
     Row {
         cells,
+        wrapped: false,
+        marks: Vec::new(),
         is_dirty: true,
         render_cache: None,
     }
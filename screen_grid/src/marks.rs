@@ -0,0 +1,62 @@
+use crate::{Point, ScreenGrid};
+
+/// Shell integration marks (OSC 133), splitting the output into prompts,
+/// commands and their output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PromptMark {
+    /// `A`: the prompt starts here
+    PromptStart,
+    /// `B`: the prompt ends and the command line starts
+    CommandStart,
+    /// `C`: the command was entered and its output starts
+    OutputStart,
+    /// `D`: the command finished, with its exit status if the shell reported it
+    CommandEnd(Option<i32>),
+}
+
+impl ScreenGrid {
+    /// Place `mark` at the cursor
+    pub fn add_mark(&mut self, mark: PromptMark) {
+        let x = self.cur_x.min(self.cols.saturating_sub(1));
+        if let Some(row) = self.visible_row_mut(self.cur_y) {
+            row.marks.push((x, mark));
+        }
+    }
+
+    /// Every mark in history and on screen, oldest first
+    pub fn marks(&self) -> impl Iterator<Item = (Point, PromptMark)> + '_ {
+        let history = self.scrollback.marked_rows();
+        let screen = self
+            .lines
+            .iter()
+            .enumerate()
+            .map(|(y, row)| (self.scrollback_len() + y, row.marks.as_slice()));
+
+        history.chain(screen).flat_map(|(line, marks)| {
+            marks
+                .iter()
+                .map(move |&(col, mark)| (Point { line, col }, mark))
+        })
+    }
+
+    /// Lines where a prompt starts, oldest first
+    pub fn prompt_lines(&self) -> Vec<usize> {
+        let mut lines: Vec<usize> = self
+            .marks()
+            .filter(|(_, mark)| *mark == PromptMark::PromptStart)
+            .map(|(point, _)| point.line)
+            .collect();
+        lines.dedup();
+        lines
+    }
+
+    /// The closest prompt line before `line`
+    pub fn previous_prompt(&self, line: usize) -> Option<usize> {
+        self.prompt_lines().into_iter().rfind(|&l| l < line)
+    }
+
+    /// The closest prompt line after `line`
+    pub fn next_prompt(&self, line: usize) -> Option<usize> {
+        self.prompt_lines().into_iter().find(|&l| l > line)
+    }
+}
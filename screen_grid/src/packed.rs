@@ -0,0 +1,244 @@
+//! Compact encoding for rows that only need to be kept around, not drawn.
+//!
+//! A packed row stores its attributes run-length encoded and its text with
+//! trailing blanks trimmed, which is usually a small fraction of the
+//! `Vec<Cell>` it came from. Prompt marks are kept in the header, after the
+//! width and wrap flag, so they go wherever the row goes
+
+use crate::{Cell, CellExtra, CellFlags, Color, PromptMark, Rgb, Row};
+
+/// Encode `row` into a byte string that `unpack` turns back into the same cells
+pub fn pack(row: &Row) -> Box<[u8]> {
+    let mut out = Vec::with_capacity(32);
+
+    put_varint(&mut out, row.cells.len() as u64);
+    out.push(u8::from(row.wrapped));
+
+    put_varint(&mut out, row.marks.len() as u64);
+    for &(x, mark) in &row.marks {
+        put_varint(&mut out, x as u64);
+        put_mark(&mut out, mark);
+    }
+
+    // Attribute runs
+    let mut runs: Vec<(usize, &Cell)> = Vec::new();
+    for cell in &row.cells {
+        match runs.last_mut() {
+            Some((len, first)) if same_attrs(first, cell) => *len += 1,
+            _ => runs.push((1, cell)),
+        }
+    }
+
+    put_varint(&mut out, runs.len() as u64);
+    for (len, cell) in runs {
+        put_varint(&mut out, len as u64);
+        put_color(&mut out, cell.fg);
+        put_color(&mut out, cell.bg);
+        put_varint(&mut out, cell.flags.bits() as u64);
+        put_varint(&mut out, cell.link_id.map_or(0, |id| id as u64 + 1));
+        match cell.underline_color {
+            Some(color) => {
+                out.push(1);
+                put_color(&mut out, color);
+            }
+            None => out.push(0),
+        }
+    }
+
+    // First code point of every cell, up to the last one that isn't blank
+    let text_len = row
+        .cells
+        .iter()
+        .rposition(|c| c.ch != ' ' || c.extra.is_some())
+        .map_or(0, |x| x + 1);
+
+    let text: String = row.cells[..text_len].iter().map(|c| c.ch).collect();
+    put_bytes(&mut out, text.as_bytes());
+
+    // The rest of any grapheme clusters, by column
+    let extras: Vec<(usize, &CellExtra)> = row.cells[..text_len]
+        .iter()
+        .enumerate()
+        .filter_map(|(x, c)| c.extra.as_deref().map(|extra| (x, extra)))
+        .collect();
+
+    put_varint(&mut out, extras.len() as u64);
+    for (x, extra) in extras {
+        put_varint(&mut out, x as u64);
+        put_bytes(&mut out, extra.zerowidth.as_bytes());
+    }
+
+    out.into_boxed_slice()
+}
+
+/// Bytes at the start of a packed row that hold its width and wrap flag
+pub const HEADER_LEN: usize = 11;
+
+/// Whether a packed row was soft-wrapped, without decoding its cells.
+/// Only the first `HEADER_LEN` bytes are needed
+pub fn is_wrapped(bytes: &[u8]) -> Option<bool> {
+    let mut reader = Reader { bytes };
+
+    reader.varint()?;
+    Some(reader.byte()? != 0)
+}
+
+/// Decode a row produced by `pack`. Returns `None` if `bytes` is malformed
+pub fn unpack(bytes: &[u8]) -> Option<Row> {
+    let mut reader = Reader { bytes };
+
+    let cols = reader.varint()? as usize;
+    let wrapped = reader.byte()? != 0;
+
+    let mut marks = Vec::new();
+    for _ in 0..reader.varint()? {
+        marks.push((reader.varint()? as usize, reader.mark()?));
+    }
+
+    let mut cells = Vec::with_capacity(cols);
+    for _ in 0..reader.varint()? {
+        let len = reader.varint()? as usize;
+        let fg = reader.color()?;
+        let bg = reader.color()?;
+        let flags = CellFlags::from_bits_truncate(reader.varint()? as u16);
+        let link_id = reader.varint()?.checked_sub(1).map(|id| id as u32);
+        let underline_color = match reader.byte()? {
+            0 => None,
+            _ => Some(reader.color()?),
+        };
+
+        let cell = Cell {
+            fg,
+            bg,
+            flags,
+            underline_color,
+            link_id,
+            ..Default::default()
+        };
+        cells.extend(std::iter::repeat_n(cell, len.min(cols - cells.len())));
+    }
+    cells.resize(cols, Cell::default());
+
+    let text = std::str::from_utf8(reader.bytes()?).ok()?;
+    for (cell, ch) in cells.iter_mut().zip(text.chars()) {
+        cell.ch = ch;
+    }
+
+    for _ in 0..reader.varint()? {
+        let x = reader.varint()? as usize;
+        let zerowidth = std::str::from_utf8(reader.bytes()?).ok()?;
+        if let Some(cell) = cells.get_mut(x) {
+            cell.extra = Some(Box::new(CellExtra {
+                zerowidth: zerowidth.to_owned(),
+            }));
+        }
+    }
+
+    Some(Row {
+        cells,
+        wrapped,
+        marks,
+        is_dirty: true,
+        render_cache: None,
+    })
+}
+
+fn same_attrs(a: &Cell, b: &Cell) -> bool {
+    a.fg == b.fg
+        && a.bg == b.bg
+        && a.flags == b.flags
+        && a.underline_color == b.underline_color
+        && a.link_id == b.link_id
+}
+
+/// LEB128: 7 bits per byte, high bit set on every byte but the last
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// A tag byte, then the palette index or the three channels
+fn put_color(out: &mut Vec<u8>, color: Color) {
+    match color {
+        Color::Default => out.push(0),
+        Color::Indexed(idx) => out.extend_from_slice(&[1, idx]),
+        Color::Rgb(Rgb(r, g, b)) => out.extend_from_slice(&[2, r, g, b]),
+    }
+}
+
+/// A tag byte, then the exit status for a command end that has one
+fn put_mark(out: &mut Vec<u8>, mark: PromptMark) {
+    match mark {
+        PromptMark::PromptStart => out.push(0),
+        PromptMark::CommandStart => out.push(1),
+        PromptMark::OutputStart => out.push(2),
+        PromptMark::CommandEnd(None) => out.push(3),
+        PromptMark::CommandEnd(Some(code)) => {
+            out.push(4);
+            put_varint(out, code as u32 as u64);
+        }
+    }
+}
+
+fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    put_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> Option<u8> {
+        let (&first, rest) = self.bytes.split_first()?;
+        self.bytes = rest;
+        Some(first)
+    }
+
+    fn varint(&mut self) -> Option<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7F) << shift;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    fn color(&mut self) -> Option<Color> {
+        match self.byte()? {
+            0 => Some(Color::Default),
+            1 => Some(Color::Indexed(self.byte()?)),
+            2 => Some(Color::Rgb(Rgb(self.byte()?, self.byte()?, self.byte()?))),
+            _ => None,
+        }
+    }
+
+    fn mark(&mut self) -> Option<PromptMark> {
+        match self.byte()? {
+            0 => Some(PromptMark::PromptStart),
+            1 => Some(PromptMark::CommandStart),
+            2 => Some(PromptMark::OutputStart),
+            3 => Some(PromptMark::CommandEnd(None)),
+            4 => Some(PromptMark::CommandEnd(Some(self.varint()? as u32 as i32))),
+            _ => None,
+        }
+    }
+
+    fn bytes(&mut self) -> Option<&'a [u8]> {
+        let len = self.varint()? as usize;
+        if len > self.bytes.len() {
+            return None;
+        }
+
+        let (head, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Some(head)
+    }
+}
@@ -0,0 +1,390 @@
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{Cell, PromptMark, Row, packed};
+
+/// Lines that have scrolled off the top of the screen, oldest first.
+///
+/// Rows are stored packed (see `packed`) in a ring buffer bounded by a
+/// byte budget rather than a line count. Only the rows currently in view
+/// are unpacked into full `Row`s, via `set_view`.
+///
+/// An unlimited scrollback moves rows that no longer fit in the budget to
+/// a temporary file instead of dropping them, so memory use stays bounded.
+/// Prompt marks are packed with their rows, so rows on disk keep theirs,
+/// but only the marks of rows in memory are indexed by `marked_rows`
+pub struct Scrollback {
+    /// Packed rows kept in memory, oldest first
+    packed: VecDeque<Box<[u8]>>,
+    /// Bytes used by `packed` and `marks`
+    bytes: usize,
+    /// Max bytes `packed` may use
+    budget: usize,
+    /// Rows older than everything in `packed`, moved out of memory
+    spill: Option<SpillFile>,
+
+    /// Unpacked copies of the history lines in view, sorted by index
+    view: Vec<(usize, Row)>,
+
+    /// Prompt marks of the rows in memory that have them, keyed by
+    /// `dropped` + index and oldest first, so they can be found without
+    /// unpacking anything
+    marks: VecDeque<(usize, Vec<(usize, PromptMark)>)>,
+    /// Rows forgotten since the history was created
+    dropped: usize,
+}
+
+impl Scrollback {
+    /// History that keeps at most `budget` bytes, dropping the oldest rows past that
+    pub fn new(budget: usize) -> Self {
+        Self {
+            packed: VecDeque::new(),
+            bytes: 0,
+            budget,
+            spill: None,
+            view: Vec::new(),
+            marks: VecDeque::new(),
+            dropped: 0,
+        }
+    }
+
+    /// History that keeps `budget` bytes in memory and everything older on disk.
+    /// Falls back to a bounded history if no temporary file can be created
+    pub fn unlimited(budget: usize) -> Self {
+        let spill = SpillFile::new()
+            .inspect_err(|e| log::warn!("Unlimited scrollback unavailable: {e}"))
+            .ok();
+
+        Self {
+            spill,
+            ..Self::new(budget)
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.spilled() + self.packed.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Lines stored on disk
+    fn spilled(&self) -> usize {
+        self.spill.as_ref().map_or(0, |spill| spill.len)
+    }
+
+    /// Add the newest row. Its storage is handed back so it can be reused
+    pub fn push(&mut self, row: Row) -> Row {
+        if self.budget == 0 && self.spill.is_none() {
+            return row;
+        }
+
+        if !row.marks.is_empty() {
+            self.bytes += marks_size(&row.marks);
+            self.marks
+                .push_back((self.dropped + self.len(), row.marks.clone()));
+        }
+
+        let packed = packed::pack(&row);
+        self.bytes += entry_size(&packed);
+        self.packed.push_back(packed);
+
+        while self.bytes > self.budget
+            && let Some(oldest) = self.packed.pop_front()
+        {
+            self.bytes -= entry_size(&oldest);
+
+            // The row's marks go with it, packed
+            let key = self.dropped + self.spilled();
+            if self.marks.front().is_some_and(|&(k, _)| k == key)
+                && let Some((_, marks)) = self.marks.pop_front()
+            {
+                self.bytes -= marks_size(&marks);
+            }
+
+            let spilled = self.spill.as_mut().is_some_and(|spill| {
+                spill
+                    .push(&oldest)
+                    .inspect_err(|e| log::warn!("Failed to move scrollback to disk: {e}"))
+                    .is_ok()
+            });
+
+            if !spilled {
+                self.forget_oldest();
+            }
+        }
+
+        row
+    }
+
+    /// Remove and unpack the newest row, if it's still in memory
+    pub fn pop(&mut self) -> Option<Row> {
+        let packed = self.packed.pop_back()?;
+        self.bytes -= entry_size(&packed);
+
+        let idx = self.len();
+        self.view.retain(|(i, _)| *i != idx);
+        if self
+            .marks
+            .back()
+            .is_some_and(|&(key, _)| key == self.dropped + idx)
+            && let Some((_, marks)) = self.marks.pop_back()
+        {
+            self.bytes -= marks_size(&marks);
+        }
+
+        Some(packed::unpack(&packed).unwrap_or_default())
+    }
+
+    /// Keep `view` in step with indices shifting down after the oldest line is dropped
+    fn forget_oldest(&mut self) {
+        let first_in_memory = self.spilled();
+
+        self.view.retain(|(idx, _)| *idx != first_in_memory);
+        for (idx, _) in &mut self.view {
+            if *idx > first_in_memory {
+                *idx -= 1;
+            }
+        }
+
+        if first_in_memory == 0 {
+            self.dropped += 1;
+        } else {
+            // A failed write to disk drops a row from the middle, after the
+            // ones already there, so only the keys of later rows shift
+            for (key, _) in &mut self.marks {
+                *key -= 1;
+            }
+        }
+    }
+
+    /// Rows forgotten since the history was created. Adding this to an index
+    /// gives a line number that stays the same as older rows are dropped
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    /// Row `idx` counted from the oldest line kept,
+    /// if it has been unpacked with `set_view`
+    pub fn get(&self, idx: usize) -> Option<&Row> {
+        let pos = self.view.binary_search_by_key(&idx, |(i, _)| *i).ok()?;
+        Some(&self.view[pos].1)
+    }
+
+    /// Unpack a copy of row `idx`, whether or not it is in view
+    pub fn unpack(&self, idx: usize) -> Option<Row> {
+        let spilled = self.spilled();
+
+        if idx < spilled {
+            let spill = self.spill.as_ref()?;
+            let bytes = spill
+                .read(idx, u64::MAX)
+                .inspect_err(|e| log::warn!("Failed to read scrollback from disk: {e}"))
+                .ok()?;
+            packed::unpack(&bytes)
+        } else {
+            packed::unpack(self.packed.get(idx - spilled)?)
+        }
+    }
+
+    /// Whether row `idx` was soft-wrapped. Only the start of a packed row
+    /// is read, so this is much cheaper than `unpack`
+    pub fn is_wrapped(&self, idx: usize) -> Option<bool> {
+        if let Some(row) = self.get(idx) {
+            return Some(row.wrapped);
+        }
+
+        let spilled = self.spilled();
+        if idx < spilled {
+            let spill = self.spill.as_ref()?;
+            let bytes = spill
+                .read(idx, packed::HEADER_LEN as u64)
+                .inspect_err(|e| log::warn!("Failed to read scrollback from disk: {e}"))
+                .ok()?;
+            packed::is_wrapped(&bytes)
+        } else {
+            packed::is_wrapped(self.packed.get(idx - spilled)?)
+        }
+    }
+
+    /// Every row with prompt marks as `(idx, marks)`, oldest first
+    pub fn marked_rows(&self) -> impl DoubleEndedIterator<Item = (usize, &[(usize, PromptMark)])> {
+        self.marks
+            .iter()
+            .map(|(key, marks)| (key - self.dropped, marks.as_slice()))
+    }
+
+    /// Unpack the rows in `lines`, which must be in ascending order, so `get` can
+    /// return them, padded with `blank` or truncated to `cols`.
+    /// Rows that left the view are packed away again
+    pub fn set_view(&mut self, lines: impl IntoIterator<Item = usize>, cols: usize, blank: &Cell) {
+        let len = self.len();
+        let lines: Vec<usize> = lines.into_iter().take_while(|&idx| idx < len).collect();
+
+        if self
+            .view
+            .iter()
+            .map(|(idx, _)| *idx)
+            .eq(lines.iter().copied())
+        {
+            return;
+        }
+
+        let mut old_view = std::mem::take(&mut self.view).into_iter().peekable();
+        let mut view = Vec::with_capacity(lines.len());
+
+        for idx in lines {
+            while old_view.next_if(|(old, _)| *old < idx).is_some() {}
+
+            let row = match old_view.next_if(|(old, _)| *old == idx) {
+                Some((_, row)) => row,
+                None => {
+                    let mut row = self.unpack(idx).unwrap_or_default();
+                    row.cells.resize(cols, blank.clone());
+                    row.is_dirty = true;
+                    row
+                }
+            };
+            view.push((idx, row));
+        }
+
+        self.view = view;
+    }
+
+    /// Rows currently unpacked for display, oldest first
+    pub fn view_mut(&mut self) -> impl DoubleEndedIterator<Item = &mut Row> {
+        self.view.iter_mut().map(|(_, row)| row)
+    }
+
+    /// Remove and unpack every row held in memory, oldest first.
+    /// Rows already moved to disk stay where they are
+    pub fn drain(&mut self) -> impl ExactSizeIterator<Item = Row> + use<> {
+        self.bytes = 0;
+        self.view.clear();
+
+        self.marks.clear();
+
+        std::mem::take(&mut self.packed)
+            .into_iter()
+            .map(|packed| packed::unpack(&packed).unwrap_or_default())
+    }
+}
+
+/// Memory used by one packed row, including its slot in the ring buffer
+fn entry_size(packed: &[u8]) -> usize {
+    packed.len() + size_of::<Box<[u8]>>()
+}
+
+/// Memory used by the index entry for one row's marks
+fn marks_size(marks: &[(usize, PromptMark)]) -> usize {
+    size_of_val(marks) + size_of::<(usize, Vec<(usize, PromptMark)>)>()
+}
+
+/// Append-only store for packed rows on disk.
+///
+/// `data` holds the rows back to back and `index` holds the offset of each
+/// one as a little-endian u64, so nothing per row needs to stay in memory.
+///
+/// The files hold terminal history, so they're created readable by the owner
+/// only, in a per-user directory where there is one. On Unix they're unlinked
+/// as soon as they're open and live on only through their handles
+struct SpillFile {
+    data: File,
+    index: File,
+    data_len: u64,
+    len: usize,
+    /// Files to remove on drop, where they couldn't be unlinked while open
+    paths: Vec<PathBuf>,
+}
+
+impl SpillFile {
+    fn new() -> io::Result<Self> {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let dir = std::env::var_os("XDG_RUNTIME_DIR")
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_dir())
+            .unwrap_or_else(std::env::temp_dir);
+        let pid = std::process::id();
+        let data_path = dir.join(format!("bnuuy-scrollback-{pid}-{id}.data"));
+        let index_path = dir.join(format!("bnuuy-scrollback-{pid}-{id}.index"));
+
+        let open = |path: &PathBuf| {
+            let mut options = OpenOptions::new();
+            options.read(true).write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            options.open(path)
+        };
+
+        let data = open(&data_path)?;
+        let index = open(&index_path).inspect_err(|_| {
+            let _ = std::fs::remove_file(&data_path);
+        })?;
+
+        let mut paths = vec![data_path, index_path];
+        if cfg!(unix) {
+            for path in paths.drain(..) {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+
+        Ok(Self {
+            data,
+            index,
+            data_len: 0,
+            len: 0,
+            paths,
+        })
+    }
+
+    fn push(&mut self, packed: &[u8]) -> io::Result<()> {
+        self.index.seek(SeekFrom::Start(self.len as u64 * 8))?;
+        self.index.write_all(&self.data_len.to_le_bytes())?;
+
+        self.data.seek(SeekFrom::Start(self.data_len))?;
+        self.data.write_all(packed)?;
+
+        self.data_len += packed.len() as u64;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Read up to `limit` bytes from the start of row `idx`
+    fn read(&self, idx: usize, limit: u64) -> io::Result<Vec<u8>> {
+        let start = self.offset(idx)?;
+        let end = if idx + 1 < self.len {
+            self.offset(idx + 1)?
+        } else {
+            self.data_len
+        };
+        let end = end.min(start.saturating_add(limit));
+
+        let mut bytes = vec![0; (end - start) as usize];
+        let mut data = &self.data;
+        data.seek(SeekFrom::Start(start))?;
+        data.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn offset(&self, idx: usize) -> io::Result<u64> {
+        let mut bytes = [0; 8];
+        let mut index = &self.index;
+        index.seek(SeekFrom::Start(idx as u64 * 8))?;
+        index.read_exact(&mut bytes)?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        for path in &self.paths {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
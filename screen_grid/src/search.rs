@@ -0,0 +1,207 @@
+use std::ops::Range;
+
+use regex::{Regex, RegexBuilder};
+
+use crate::{CellFlags, ScreenGrid};
+
+/// A cell position. `line` counts from the oldest history line,
+/// so visible row `y` is line `scrollback_len() + y`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Point {
+    pub line: usize,
+    pub col: usize,
+}
+
+/// Cells covered by a search match, both ends inclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SearchMatch {
+    pub start: Point,
+    pub end: Point,
+}
+
+impl SearchMatch {
+    /// Whether the match covers the cell at `point`
+    pub fn contains(&self, point: Point) -> bool {
+        self.start <= point && point <= self.end
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Towards newer lines
+    Forward,
+    /// Towards older lines
+    Backward,
+}
+
+/// A compiled search query.
+/// Queries without uppercase letters match case-insensitively
+#[derive(Debug, Clone)]
+pub struct RegexSearch {
+    regex: Regex,
+}
+
+impl RegexSearch {
+    pub fn new(pattern: &str) -> Result<Self, regex::Error> {
+        let regex = RegexBuilder::new(pattern)
+            .case_insensitive(!pattern.chars().any(char::is_uppercase))
+            .build()?;
+
+        Ok(Self { regex })
+    }
+
+    /// Search for `text` as is, without any regex syntax
+    pub fn literal(text: &str) -> Self {
+        Self::new(&regex::escape(text)).expect("escaped text is a valid regex")
+    }
+}
+
+/// One line of text as it was printed, joined back from soft-wrapped rows
+struct LogicalLine {
+    text: String,
+    /// For every glyph: its byte offset in `text` and the cells it covers
+    glyphs: Vec<(usize, Point, Point)>,
+}
+
+impl LogicalLine {
+    fn matches(&self, search: &RegexSearch) -> Vec<SearchMatch> {
+        search
+            .regex
+            .find_iter(&self.text)
+            .filter(|m| !m.is_empty())
+            .map(|m| SearchMatch {
+                start: self.glyph_at(m.start()).1,
+                end: self.glyph_at(m.end() - 1).2,
+            })
+            .collect()
+    }
+
+    /// The glyph whose text contains byte `offset`
+    fn glyph_at(&self, offset: usize) -> &(usize, Point, Point) {
+        let idx = self.glyphs.partition_point(|&(start, ..)| start <= offset);
+        &self.glyphs[idx.saturating_sub(1)]
+    }
+}
+
+impl ScreenGrid {
+    /// Find the next match for `search` starting after `origin` (or before it,
+    /// when searching backward), wrapping around at either end of history.
+    /// With `max_lines`, gives up after looking through that many lines
+    pub fn search(
+        &self,
+        search: &RegexSearch,
+        origin: Point,
+        direction: Direction,
+        max_lines: Option<usize>,
+    ) -> Option<SearchMatch> {
+        let total = self.total_lines();
+        if total == 0 {
+            return None;
+        }
+
+        let origin_line = origin.line.min(total - 1);
+        let mut lines = self.logical_line_range(origin_line);
+        let mut wrapped = false;
+        let mut searched = 0;
+
+        loop {
+            let line = self.logical_line(lines.clone());
+            let mut matches = line.matches(search).into_iter();
+
+            let found = match direction {
+                Direction::Forward => matches.find(|m| wrapped || m.start > origin),
+                Direction::Backward => matches.rfind(|m| wrapped || m.start < origin),
+            };
+            if found.is_some() {
+                return found;
+            }
+
+            searched += lines.len();
+            if (wrapped && lines.contains(&origin_line))
+                || max_lines.is_some_and(|max| searched >= max)
+            {
+                return None;
+            }
+
+            lines = match direction {
+                Direction::Forward if lines.end < total => self.logical_line_range(lines.end),
+                Direction::Backward if lines.start > 0 => self.logical_line_range(lines.start - 1),
+                Direction::Forward => {
+                    wrapped = true;
+                    self.logical_line_range(0)
+                }
+                Direction::Backward => {
+                    wrapped = true;
+                    self.logical_line_range(total - 1)
+                }
+            };
+        }
+    }
+
+    /// Every match for `search` that touches `lines`
+    pub fn search_lines(&self, search: &RegexSearch, lines: Range<usize>) -> Vec<SearchMatch> {
+        let lines = lines.start..lines.end.min(self.total_lines());
+        let mut found = Vec::new();
+        let mut next = lines.start;
+
+        while next < lines.end {
+            let range = self.logical_line_range(next);
+            next = range.end;
+
+            found.extend(
+                self.logical_line(range)
+                    .matches(search)
+                    .into_iter()
+                    .filter(|m| m.end.line >= lines.start && m.start.line < lines.end),
+            );
+        }
+
+        found
+    }
+
+    /// The lines making up the logical line that `line` belongs to
+    fn logical_line_range(&self, line: usize) -> Range<usize> {
+        let mut start = line;
+        while start > 0 && self.is_wrapped(start - 1) {
+            start -= 1;
+        }
+
+        let mut end = line + 1;
+        while end < self.total_lines() && self.is_wrapped(end - 1) {
+            end += 1;
+        }
+
+        start..end
+    }
+
+    fn logical_line(&self, lines: Range<usize>) -> LogicalLine {
+        let mut text = String::new();
+        let mut glyphs = Vec::new();
+
+        for line in lines {
+            let Some(row) = self.row_at_line(line) else {
+                break;
+            };
+
+            for (col, cell) in row.cells.iter().enumerate() {
+                if cell
+                    .flags
+                    .intersects(CellFlags::WIDE_CHAR_SPACER | CellFlags::LEADING_WIDE_CHAR_SPACER)
+                {
+                    continue;
+                }
+
+                let start = Point { line, col };
+                let end = Point {
+                    line,
+                    col: col + usize::from(cell.flags.contains(CellFlags::WIDE_CHAR)),
+                };
+
+                glyphs.push((text.len(), start, end));
+                cell.push_grapheme(&mut text);
+            }
+        }
+
+        LogicalLine { text, glyphs }
+    }
+}